@@ -0,0 +1,183 @@
+use super::{
+    ActorAccessStatus,
+    ReleaseMode,
+    UPGRADED,
+    UPGRADE_PENDING,
+    ACCESS_COUNT_MASK,
+};
+use super::dispatch::{AccessSync, SharedBlocked, shared_blocked_by};
+use crate::util::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+/// The memory which an actor's guards share with its routine, through which they
+/// synchronize access to the actor's user state.
+///
+/// Every atomic operation of the access protocol is a method of this, so that the
+/// guards and the routine only synchronize through it, and so that it can be model
+/// checked with loom by building with `--cfg loom`.
+pub struct AccessState {
+    // the current number of guards accessing (mutably or immutably) the user state
+    // the high bits of this are the `UPGRADED` and `UPGRADE_PENDING` flags
+    access_count: AtomicU32,
+    // atomic memory for an actor guard to tell the internal actor procedure that
+    // the guard is releasing in some relevant non-default way
+    release_mode: AtomicU8,
+}
+
+/// What releasing a shared guard requires the releasing thread to notify.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SharedRelease {
+    /// Other guards remain, and nobody is waiting for this one.
+    Other,
+    /// This was the last guard, so the actor routine must be notified.
+    Last,
+    /// Only a guard which is waiting to upgrade remains, so it must be notified.
+    UpgradeReady,
+}
+
+impl AccessState {
+    pub fn new() -> Self {
+        AccessState {
+            access_count: AtomicU32::new(0),
+            release_mode: AtomicU8::new(ReleaseMode::Normal as u8),
+        }
+    }
+
+    /// Begin exclusive access, from the actor routine, while the actor is available.
+    pub fn begin_mut(&self) {
+        let previous_access_count = self.access_count.swap(1, Ordering::Relaxed);
+        debug_assert_eq!(previous_access_count, 0);
+    }
+
+    /// Begin another shared access, from the actor routine, unless a guard is
+    /// upgrading, or unless the guards of the current shared access have all been
+    /// released.
+    ///
+    /// In the latter case, one of them may have upgraded and deleted the actor after
+    /// the routine observed the release mode, so it must observe it again.
+    pub fn begin_shared(&self, access_status: ActorAccessStatus) -> Result<(), SharedBlocked> {
+        let mut access_count = self.access_count.load(Ordering::Relaxed);
+        loop {
+            if let Some(blocked) = shared_blocked_by(access_count, access_status) {
+                return Err(blocked);
+            }
+            match self.access_count.compare_exchange_weak(
+                access_count,
+                access_count + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(current) => access_count = current,
+            }
+        }
+    }
+
+    /// Add a guard to the current shared access, for a cloned shared guard.
+    pub fn clone_shared(&self) {
+        self.access_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Release a shared guard.
+    pub fn release_shared(&self) -> SharedRelease {
+        // releases this guard's reads to whoever next accesses the user state mutably
+        let previous_access_count = self.access_count.fetch_sub(1, Ordering::Release);
+        if previous_access_count == 1 {
+            SharedRelease::Last
+        } else if previous_access_count == UPGRADE_PENDING | 2 {
+            SharedRelease::UpgradeReady
+        } else {
+            SharedRelease::Other
+        }
+    }
+
+    /// Release the exclusive guard, releasing its writes to the user state to the
+    /// actor routine.
+    pub fn release_mut(&self) {
+        // since we have exclusive access, this should decrement it to 0
+        let previous_access_count = self.access_count.swap(0, Ordering::Release);
+        debug_assert_eq!(previous_access_count & ACCESS_COUNT_MASK, 1);
+    }
+
+    /// Downgrade the exclusive guard to a shared guard.
+    pub fn downgrade(&self) {
+        // releases this guard's writes to the shared accesses which the routine begins
+        self.release_mode.store(ReleaseMode::Downgrade as u8, Ordering::Release);
+        // if this guard was upgraded from shared, it is no longer
+        self.access_count.fetch_and(!UPGRADED, Ordering::Release);
+    }
+
+    /// Mark the exclusive guard as deleting the actor, before it releases.
+    pub fn mark_deleted(&self) {
+        // releases the extraction of the user state to the routine, whose acquiring
+        // swap of the release mode reads this, since the routine only swaps it after
+        // acquiring the release of the access count, which happens after this
+        self.release_mode.store(ReleaseMode::Delete as u8, Ordering::Release);
+    }
+
+    /// Upgrade the only shared guard to an exclusive guard, if it is the only one.
+    pub fn try_upgrade(&self) -> bool {
+        self.access_count.compare_exchange(
+            1,
+            UPGRADED | 1,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ).is_ok()
+    }
+
+    /// Claim the pending upgrade for a shared guard, which blocks the actor routine
+    /// from beginning new shared accesses.
+    ///
+    /// Fails if another guard has already claimed it.
+    pub fn claim_upgrade(&self) -> bool {
+        let previous = self.access_count.fetch_or(UPGRADE_PENDING, Ordering::Relaxed);
+        previous & UPGRADE_PENDING == 0
+    }
+
+    /// Complete the claimed upgrade, if every other shared guard has released.
+    pub fn complete_upgrade(&self) -> bool {
+        self.access_count.compare_exchange(
+            UPGRADE_PENDING | 1,
+            UPGRADED | 1,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ).is_ok()
+    }
+
+    /// Abandon the claimed upgrade, before the claiming guard releases.
+    pub fn abandon_upgrade(&self) {
+        self.access_count.fetch_and(!UPGRADE_PENDING, Ordering::Relaxed);
+    }
+}
+
+impl AccessSync for AccessState {
+    fn load_access_count(&self) -> u32 {
+        self.access_count.load(Ordering::Acquire)
+    }
+
+    fn swap_release_mode(&self, mode: ReleaseMode) -> ReleaseMode {
+        // the routine observes the release mode on every poll, and it is usually
+        // normal, in which case there is nothing to reset, so it is only loaded.
+        // this acquires a guard's store like the swap does, and reading normal
+        // is equivalent to swapping normal for normal just before any store which
+        // it misses, which the storing guard notifies the routine of on release
+        if mode == ReleaseMode::Normal {
+            let current = self.release_mode.load(Ordering::Acquire);
+            if current == ReleaseMode::Normal as u8 {
+                return ReleaseMode::Normal;
+            }
+        }
+        ReleaseMode::from_u8(self.release_mode.swap(mode as u8, Ordering::AcqRel))
+    }
+}
+
+impl ReleaseMode {
+    fn from_u8(mode: u8) -> Self {
+        // only valid release modes are ever stored
+        debug_assert!(mode <= ReleaseMode::Delete as u8, "invalid release mode {}", mode);
+        match mode {
+            0 => ReleaseMode::Normal,
+            1 => ReleaseMode::Downgrade,
+            _ => ReleaseMode::Delete,
+        }
+    }
+}
@@ -1,28 +1,244 @@
 
 use crate::Actor;
-use crate::manage::{SubordinateActor, ActorSocket};
-use crate::mailbox::MailboxOwned;
-use super::{ActorGuardShared, ActorGuardMut, ReleaseMode};
+use crate::manage::{
+    SubordinateActor,
+    SubordinateHandle,
+    ActorSocket,
+    ChildInfo,
+    ActorId,
+    ActorPath,
+    Registry,
+    NameTaken,
+    ExitReason,
+    ActorSystem,
+};
+use crate::mailbox::{MailboxOwned, generic::Mailbox, ownership::Ownership};
+use crate::msg_union::MessageTypeUnion;
+use super::{
+    ActorGuardShared,
+    ActorGuardMut,
+    ActorGuardUpgrade,
+    MappedActorGuardShared,
+    MappedActorGuardMut,
+};
+use super::access::SharedRelease;
 use super::supervise::{
     create_subordinate,
     create_subordinate_socket,
+    spawn_subordinate,
+    spawn_subordinate_blocking,
+    watch,
+    unwatch,
+    link,
+    unlink,
 };
 
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 use std::mem;
 use std::hint::unreachable_unchecked;
 
-use atomic::Ordering;
+use futures::{Future, Poll, Async};
+
+/// Methods which do not depend on the kind of access, shared by both actor guards.
+///
+/// These only use the guard's `shared_state`, and are expanded into the inherent
+/// impls of `ActorGuardMut` and `ActorGuardShared`, so that both guards have the
+/// same API for managing the actor.
+macro_rules! guard_methods {
+    () => {
+        /// Create a subordinate actor, with this one as its manager.
+        ///
+        /// Returns a future which must be spawned, the subordinate mailbox, and a
+        /// handle with which this actor can stop the subordinate.
+        pub fn manage<Sub>(&self, state: Sub) -> (
+            SubordinateActor<Sub>,
+            MailboxOwned<Sub::Message>,
+            SubordinateHandle<Sub>,
+        )
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            create_subordinate(self.shared_state.as_ref(), None, state)
+        }
+
+        /// Create a named subordinate actor, with this one as its manager.
+        ///
+        /// Returns a future which must be spawned, the subordinate mailbox, and a
+        /// handle with which this actor can stop the subordinate.
+        pub fn manage_named<Sub>(&self, name: impl Into<String>, state: Sub) -> (
+            SubordinateActor<Sub>,
+            MailboxOwned<Sub::Message>,
+            SubordinateHandle<Sub>,
+        )
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            create_subordinate(self.shared_state.as_ref(), Some(name.into()), state)
+        }
+
+        /// Create a subordinate actor, with this one as its manager.
+        ///
+        /// Returns the subordinate actor socket, the subordinate mailbox, and a
+        /// handle with which this actor can stop the subordinate.
+        pub fn manage_socket<Sub>(&self) -> (
+            ActorSocket<Sub>,
+            MailboxOwned<Sub::Message>,
+            SubordinateHandle<Sub>,
+        )
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            create_subordinate_socket(self.shared_state.as_ref())
+        }
+
+        /// Information about this actor's live subordinates.
+        pub fn children(&self) -> Vec<ChildInfo> {
+            self.shared_state.subordinate_infos()
+        }
+
+        /// The actor system which this actor belongs to, if any.
+        pub fn system(&self) -> Option<&ActorSystem> {
+            self.shared_state.system.as_ref()
+        }
+
+        /// Create a subordinate actor, with this one as its manager, and spawn it onto
+        /// the spawner of this actor's system.
+        ///
+        /// # Panics
+        ///
+        /// Panics if this actor does not belong to an `ActorSystem`.
+        pub fn spawn<Sub>(&self, state: Sub) -> MailboxOwned<Sub::Message>
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            spawn_subordinate(self.shared_state.as_ref(), None, state)
+        }
+
+        /// Create a named subordinate actor, with this one as its manager, and spawn
+        /// it onto the spawner of this actor's system.
+        ///
+        /// # Panics
+        ///
+        /// Panics if this actor does not belong to an `ActorSystem`.
+        pub fn spawn_named<Sub>(&self, name: impl Into<String>, state: Sub) -> MailboxOwned<Sub::Message>
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            spawn_subordinate(self.shared_state.as_ref(), Some(name.into()), state)
+        }
+
+        /// Create a subordinate actor, with this one as its manager, and spawn it onto
+        /// a dedicated thread, for an actor which blocks in its message handlers.
+        ///
+        /// Unlike `spawn`, this does not require this actor to belong to an
+        /// `ActorSystem`.
+        pub fn spawn_blocking<Sub>(&self, state: Sub) -> MailboxOwned<Sub::Message>
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            spawn_subordinate_blocking(self.shared_state.as_ref(), None, state)
+        }
+
+        /// Create a named subordinate actor, with this one as its manager, and spawn
+        /// it onto a dedicated thread, for an actor which blocks in its message handlers.
+        pub fn spawn_blocking_named<Sub>(&self, name: impl Into<String>, state: Sub) -> MailboxOwned<Sub::Message>
+        where
+            Sub: Actor<End = Act::SubordinateEnd>
+        {
+            spawn_subordinate_blocking(self.shared_state.as_ref(), Some(name.into()), state)
+        }
+
+        /// This actor's unique id.
+        pub fn id(&self) -> ActorId {
+            self.shared_state.id
+        }
+
+        /// This actor's hierarchical path.
+        pub fn path(&self) -> &ActorPath {
+            &self.shared_state.path
+        }
+
+        /// The registry of this actor's tree.
+        pub fn registry(&self) -> &Registry {
+            &self.shared_state.registry
+        }
+
+        /// Register this actor under a name in its tree's registry.
+        ///
+        /// The name is removed from the registry once the actor terminates.
+        pub fn register(&self, name: impl Into<String>) -> Result<(), NameTaken> {
+            let shared = &self.shared_state;
+            shared.registry.register::<Act>(name.into(), shared.mailbox.clone())
+        }
+
+        /// Remove a name from the registry, if it is registered to this actor.
+        ///
+        /// Returns whether the name was removed.
+        pub fn unregister(&self, name: &str) -> bool {
+            self.shared_state.registry.unregister(name, self.shared_state.id)
+        }
+
+        /// Watch another actor, to receive a mut message when it terminates.
+        ///
+        /// The message is created from the other actor's exit reason. If the other
+        /// actor already terminated, the message is sent immediately. Watching an
+        /// actor again replaces the previous watch.
+        pub fn watch<T, O, F>(&self, mailbox: &Mailbox<T, O>, f: F)
+        where
+            T: MessageTypeUnion,
+            O: Ownership,
+            F: FnOnce(ExitReason) -> <Act::Message as MessageTypeUnion>::MutUnion + Send + 'static,
+        {
+            watch(&self.shared_state, mailbox, f)
+        }
+
+        /// Stop watching another actor.
+        pub fn unwatch<T: MessageTypeUnion, O: Ownership>(&self, mailbox: &Mailbox<T, O>) {
+            unwatch(&self.shared_state, mailbox)
+        }
+
+        /// Link this actor with another actor.
+        ///
+        /// If either actor terminates abnormally, by being killed, orphaned, or
+        /// dropped, the other is killed as well, unless it traps exits. If the other
+        /// actor already terminated, this behaves as if it just terminated.
+        pub fn link<T: MessageTypeUnion, O: Ownership>(&self, mailbox: &Mailbox<T, O>) {
+            link(&self.shared_state, mailbox)
+        }
+
+        /// Remove the link between this actor and another actor.
+        pub fn unlink<T: MessageTypeUnion, O: Ownership>(&self, mailbox: &Mailbox<T, O>) {
+            unlink(&self.shared_state, mailbox)
+        }
+
+        /// Trap the exits of linked actors.
+        ///
+        /// Rather than being killed by the abnormal exits of linked actors, this actor
+        /// receives every exit of a linked actor as a mut message, created from the
+        /// linked actor's id and exit reason.
+        pub fn trap_exits<F>(&self, f: F)
+        where
+            F: Fn(ActorId, ExitReason) -> <Act::Message as MessageTypeUnion>::MutUnion + Send + Sync + 'static,
+        {
+            self.shared_state.linked_exits.set_trap(Some(Arc::new(f)));
+        }
+
+        /// Stop trapping the exits of linked actors.
+        pub fn untrap_exits(&self) {
+            self.shared_state.linked_exits.set_trap(None);
+        }
+    };
+}
+
 
 impl<Act: Actor> ActorGuardMut<Act> {
     /// Downgrade from mutable to shared.
     ///
     /// This unblocks other concurrent actor accesses.
     pub fn downgrade(guard: Self) -> ActorGuardShared<Act> {
-        guard.shared_state.release_mode.store(
-            ReleaseMode::Downgrade,
-            Ordering::Relaxed,
-        );
+        // this also clears the UPGRADED flag, if this guard was upgraded from shared
+        guard.shared_state.access.downgrade();
 
         // notify task so that more messages can be processed
         guard.dispatch_task.notify();
@@ -38,13 +254,20 @@ impl<Act: Actor> ActorGuardMut<Act> {
         }
     }
 
+    /// Project this guard into some part of the actor's state.
+    pub fn map<T, F>(mut guard: Self, f: F) -> MappedActorGuardMut<Act, T>
+    where
+        T: ?Sized + 'static,
+        F: FnOnce(&mut Act) -> &mut T,
+    {
+        let ptr: *mut T = f(&mut *guard);
+        MappedActorGuardMut { guard, ptr }
+    }
+
     /// Delete this actor, and extract the inner state.
     pub fn delete(guard: Self, end: Act::End) -> Act {
         // send the end message to this actor's manager
-        let res = guard.shared_state.end_signal_send.unbounded_send(end);
-        if res.is_err() {
-            trace!("actor was explicitly terminated, but parent is already dead");
-        }
+        guard.shared_state.send_end(end);
 
         // extract user state
         // be careful, because this invalidates our internal ptr
@@ -58,10 +281,7 @@ impl<Act: Actor> ActorGuardMut<Act> {
 
         // if this release mode isn't set, the old memory for the user state
         // may be invalidly accessed
-        guard.shared_state.release_mode.store(
-            ReleaseMode::Delete,
-            Ordering::Relaxed,
-        );
+        guard.shared_state.access.mark_deleted();
 
         // the dropping of self will handle task notification
         mem::drop(guard);
@@ -69,84 +289,193 @@ impl<Act: Actor> ActorGuardMut<Act> {
         user_state
     }
 
-    /// Create a subordinate actor, with this one as its manager.
+    guard_methods!();
+}
+
+impl<Act: Actor> ActorGuardShared<Act> {
+    /// Attempt to upgrade from shared to mutable.
     ///
-    /// Returns the subordinate mailbox, and a future which must be spawned.
-    pub fn manage<Sub>(&self, state: Sub) -> (
-        SubordinateActor<Sub>,
-        MailboxOwned<Sub::Message>
-    )
+    /// This only succeeds if this is the only shared guard to the actor. Otherwise,
+    /// the guard is returned.
+    pub fn try_upgrade(guard: Self) -> Result<ActorGuardMut<Act>, Self> {
+        if guard.shared_state.access.try_upgrade() {
+            Ok(unsafe { Self::upgrade_unchecked(guard) })
+        } else {
+            Err(guard)
+        }
+    }
+
+    /// Project this guard into some part of the actor's state.
+    pub fn map<T, F>(guard: Self, f: F) -> MappedActorGuardShared<Act, T>
     where
-        Sub: Actor<End = Act::SubordinateEnd>
+        T: ?Sized + 'static,
+        F: FnOnce(&Act) -> &T,
     {
-        create_subordinate(self.shared_state.as_ref(), state)
+        let ptr: *const T = f(&*guard);
+        MappedActorGuardShared { guard, ptr }
     }
 
-    /// Create a subordinate actor, with this one as its manager.
+    /// Upgrade from shared to mutable, once all other shared guards release.
     ///
-    /// Returns the subordinate mailbox, and the subordinate actor socket.
-    pub fn manage_socket<Sub>(&self) -> (
-        ActorSocket<Sub>,
-        MailboxOwned<Sub::Message>,
-    )
+    /// While the upgrade is pending, the actor will not begin processing new
+    /// shared messages.
+    pub fn upgrade(guard: Self) -> ActorGuardUpgrade<Act> {
+        ActorGuardUpgrade {
+            guard: Some(guard),
+            pending: false,
+        }
+    }
+
+    /// Convert into a mutable guard, after the `UPGRADED` flag has been set.
+    unsafe fn upgrade_unchecked(guard: Self) -> ActorGuardMut<Act> {
+        // like with downgrading, we rely on the guards' repr(C)
+        let mut guard = mem::transmute::<
+            ActorGuardShared<Act>,
+            ActorGuardMut<Act>,
+        >(guard);
+
+        // re-derive the pointer, since the old one was a shared alias
+        let cell: *mut Option<Act> = guard.shared_state.user_state.get();
+        guard.ptr = match *cell {
+            Some(ref mut act) => act as *mut Act,
+            None => unreachable_unchecked(),
+        };
+
+        guard
+    }
+
+    guard_methods!();
+}
+
+
+impl<Act: Actor, T: ?Sized> MappedActorGuardShared<Act, T> {
+    /// Further project this guard into some part of its target.
+    pub fn map<U, F>(guard: Self, f: F) -> MappedActorGuardShared<Act, U>
     where
-        Sub: Actor<End = Act::SubordinateEnd>
+        U: ?Sized + 'static,
+        F: FnOnce(&T) -> &U,
     {
-        create_subordinate_socket(self.shared_state.as_ref())
+        let ptr: *const U = f(&*guard);
+        MappedActorGuardShared {
+            guard: guard.guard,
+            ptr,
+        }
+    }
+
+    /// Discard the projection, getting back the guard to the whole actor.
+    pub fn unmap(guard: Self) -> ActorGuardShared<Act> {
+        guard.guard
     }
 }
 
-impl<Act: Actor> ActorGuardShared<Act> {
-    /// Create a subordinate actor, with this one as its manager.
-    ///
-    /// Returns the subordinate mailbox, and a future which must be spawned.
-    pub fn manage<Sub>(&self, state: Sub) -> (
-        SubordinateActor<Sub>,
-        MailboxOwned<Sub::Message>
-    )
+impl<Act: Actor, T: ?Sized> MappedActorGuardMut<Act, T> {
+    /// Further project this guard into some part of its target.
+    pub fn map<U, F>(mut guard: Self, f: F) -> MappedActorGuardMut<Act, U>
     where
-        Sub: Actor<End = Act::SubordinateEnd>
+        U: ?Sized + 'static,
+        F: FnOnce(&mut T) -> &mut U,
     {
-        create_subordinate(self.shared_state.as_ref(), state)
+        let ptr: *mut U = f(&mut *guard);
+        MappedActorGuardMut {
+            guard: guard.guard,
+            ptr,
+        }
     }
 
-    /// Create a subordinate actor, with this one as its manager.
+    /// Downgrade from mutable to shared, keeping the projection.
     ///
-    /// Returns the subordinate mailbox, and the subordinate actor socket.
-    pub fn manage_socket<Sub>(&self) -> (
-        ActorSocket<Sub>,
-        MailboxOwned<Sub::Message>,
-    )
-        where
-            Sub: Actor<End = Act::SubordinateEnd>
-    {
-        create_subordinate_socket(self.shared_state.as_ref())
+    /// This unblocks other concurrent actor accesses.
+    pub fn downgrade(guard: Self) -> MappedActorGuardShared<Act, T> {
+        MappedActorGuardShared {
+            guard: ActorGuardMut::downgrade(guard.guard),
+            ptr: guard.ptr as *const T,
+        }
+    }
+
+    /// Discard the projection, getting back the guard to the whole actor.
+    pub fn unmap(guard: Self) -> ActorGuardMut<Act> {
+        guard.guard
     }
 }
 
+impl<Act: Actor> Future for ActorGuardUpgrade<Act> {
+    type Item = ActorGuardMut<Act>;
+    type Error = ActorGuardShared<Act>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // take self.guard, and un-take it if we yield
+        let guard = match self.guard.take() {
+            Some(guard) => guard,
+            None => {
+                warn!("ActorGuardUpgrade.guard is None, was ActorGuardUpgrade::poll called after completion?");
+                return Ok(Async::NotReady);
+            },
+        };
+        let shared_state = &guard.shared_state;
+
+        // claim the pending upgrade, which blocks the actor from beginning new
+        // shared accesses
+        if !self.pending {
+            if !shared_state.access.claim_upgrade() {
+                trace!("{}: another actor guard is already upgrading, upgrade failed", shared_state.path);
+                return Err(guard);
+            }
+            self.pending = true;
+        }
+
+        // register before checking, so that we don't miss the last release
+        shared_state.upgrade_task.register();
+        if shared_state.access.complete_upgrade() {
+            trace!("{}: actor guard upgraded", shared_state.path);
+            self.pending = false;
+            Ok(Async::Ready(unsafe { ActorGuardShared::upgrade_unchecked(guard) }))
+        } else {
+            self.guard = Some(guard);
+            Ok(Async::NotReady)
+        }
+    }
+}
 
 // == drop impls ==
 
 impl<Act: Actor> Drop for ActorGuardShared<Act> {
     fn drop(&mut self) {
         // decrement the access_count, and if we've lowered it to 0, notify the task
-        let previous_access_count = self.shared_state.access_count.fetch_sub(1, Ordering::Relaxed);
-        if previous_access_count == 1 {
-            trace!("access count lowered to 0, notifying dispatch task");
-            self.dispatch_task.notify();
+        match self.shared_state.access.release_shared() {
+            SharedRelease::Other => (),
+            SharedRelease::Last => {
+                trace!("{}: access count lowered to 0, notifying dispatch task", self.shared_state.path);
+                self.dispatch_task.notify();
+            },
+            SharedRelease::UpgradeReady => {
+                trace!("{}: access count lowered to 1, notifying upgrading task", self.shared_state.path);
+                self.shared_state.upgrade_task.notify();
+            },
+        };
+    }
+}
+
+impl<Act: Actor> Drop for ActorGuardUpgrade<Act> {
+    fn drop(&mut self) {
+        // if we're abandoning a pending upgrade, release the claim on it before our
+        // guard releases, and notify the actor routine, which may resume beginning
+        // shared accesses while other guards remain
+        if self.pending {
+            if let Some(ref guard) = self.guard {
+                trace!("{}: pending upgrade abandoned", guard.shared_state.path);
+                guard.shared_state.access.abandon_upgrade();
+                guard.dispatch_task.notify();
+            }
         }
     }
 }
 
 impl<Act: Actor> Drop for ActorGuardMut<Act> {
     fn drop(&mut self) {
-        // decrement the access_count
-        // since we have exclusive access, this should decrement it to 0
         // atomic-release our user_state writes to the dispatch task
-        let previous_access_count = self.shared_state.access_count.swap(0, Ordering::Release);
-        debug_assert_eq!(previous_access_count, 1);
+        self.shared_state.access.release_mut();
 
-        trace!("exclusive actor guard released, notifying dispatch task");
+        trace!("{}: exclusive actor guard released, notifying dispatch task", self.shared_state.path);
         self.dispatch_task.notify();
     }
 }
@@ -155,7 +484,7 @@ impl<Act: Actor> Drop for ActorGuardMut<Act> {
 impl<Act: Actor> Clone for ActorGuardShared<Act> {
     fn clone(&self) -> Self {
         // increment the access count, then the rest is straight-forward cloning
-        self.shared_state.access_count.fetch_add(1, Ordering::Relaxed);
+        self.shared_state.access.clone_shared();
 
         ActorGuardShared {
             shared_state: self.shared_state.clone(),
@@ -165,6 +494,15 @@ impl<Act: Actor> Clone for ActorGuardShared<Act> {
     }
 }
 
+impl<Act: Actor, T: ?Sized> Clone for MappedActorGuardShared<Act, T> {
+    fn clone(&self) -> Self {
+        MappedActorGuardShared {
+            guard: self.guard.clone(),
+            ptr: self.ptr,
+        }
+    }
+}
+
 // == deref impls ==
 
 impl<Act: Actor> Deref for ActorGuardShared<Act> {
@@ -194,3 +532,31 @@ impl<Act: Actor> DerefMut for ActorGuardMut<Act> {
         }
     }
 }
+
+impl<Act: Actor, T: ?Sized> Deref for MappedActorGuardShared<Act, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            &*self.ptr
+        }
+    }
+}
+
+impl<Act: Actor, T: ?Sized> Deref for MappedActorGuardMut<Act, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            &*self.ptr
+        }
+    }
+}
+
+impl<Act: Actor, T: ?Sized> DerefMut for MappedActorGuardMut<Act, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.ptr
+        }
+    }
+}
@@ -1,24 +1,54 @@
-
 use super::*;
-use super::queue::MsgQueue;
-use crate::msg_union::ActorMailboxEntry;
-use crate::util::drop_signal::{DropSignalSend, drop_signal_channel};
+use super::queue::{MsgQueue, SystemSend};
+use super::watch::{ExitWatchers, LinkedExits};
+use super::supervise::{SupervisorLink, EndSignalSend};
+use crate::util::drop_signal::{DropSignalArc, DropSignalArcWeak, drop_signal_channel};
+use crate::mailbox::{MailboxWeak, generic::Mailbox, ownership::Weak};
+use crate::manage::ActorMetrics;
 
-/// Set up the internal concurrency mechanism for an actor.
-///
-/// Returns:
-/// 1. the actor state, which, itself, is the dispatch task future
-/// 2. a message sender handle
-/// 3. the drop signal sender which signals that the actor is orphaned
-pub fn create_actor<Act: Actor>(
-    user_state: Act,
-    end_signal_send: mpsc::UnboundedSender<Act::End>
-) -> (
-    ActorState<Act>,
-    mpsc::Sender<ActorMailboxEntry<Act>>,
-    DropSignalSend,
-) {
-    // create the message channels
+use futures::sync::oneshot;
+
+/// The handles of a newly created actor.
+pub struct CreatedActor<Act: Actor> {
+    /// The actor state, which, itself, is the dispatch task future.
+    pub state: ActorState<Act>,
+    /// A mailbox to the actor, from which owned mailboxes can be made.
+    pub mailbox: MailboxWeak<Act::Message>,
+    /// The drop signal sender which signals that the actor is orphaned.
+    pub kill_signal_send: DropSignalArc,
+    /// The sender for an end value to be emitted if the actor is stopped.
+    pub stop_send: oneshot::Sender<Act::End>,
+}
+
+/// The receiving ends of an actor's channels, into which the actor state can
+/// be plugged.
+pub struct ActorInbox<Act: Actor> {
+    /// The actor's message queue.
+    pub msg_recv: MsgQueue<Act>,
+    /// A mailbox to the actor.
+    pub mailbox: MailboxWeak<Act::Message>,
+    /// The sender to be cloned out to subordinates, for their end values.
+    pub subord_end_signal_send: EndSignalSend<Act::SubordinateEnd>,
+    /// The sender of the actor's system message channel.
+    pub system_send: SystemSend<Act>,
+    /// The actor's end of its links.
+    pub linked_exits: Arc<LinkedExits<Act>>,
+    /// The kill signal of the actor, which does not keep it alive.
+    pub kill_signal: DropSignalArcWeak,
+}
+
+/// The channels of a newly created actor.
+pub struct CreatedChannels<Act: Actor> {
+    /// The receiving ends.
+    pub inbox: ActorInbox<Act>,
+    /// The drop signal sender which signals that the actor is orphaned.
+    pub kill_signal_send: DropSignalArc,
+    /// The sender for an end value to be emitted if the actor is stopped.
+    pub stop_send: oneshot::Sender<Act::End>,
+}
+
+/// Create the channels of an actor, for which the link has been created.
+pub fn create_channels<Act: Actor>(link: &SupervisorLink<Act>) -> CreatedChannels<Act> {
     let (
         mailbox_send,
         mailbox_recv
@@ -27,47 +57,106 @@ pub fn create_actor<Act: Actor>(
         sub_end_send,
         sub_end_recv
     ) = mpsc::unbounded();
+    let (
+        system_send,
+        system_recv,
+    ) = mpsc::unbounded();
     let (
         kil_sig_send,
         kil_sig_recv,
     ) = drop_signal_channel();
+    let (
+        stop_send,
+        stop_recv,
+    ) = oneshot::channel();
 
     let msg_recv = MsgQueue::new(
         kil_sig_recv,
+        stop_recv,
         mailbox_recv,
         sub_end_recv,
+        system_recv,
+    );
+    let kil_sig_send = kil_sig_send.arc();
+    let linked_exits = Arc::new(LinkedExits::new(
+        kil_sig_send.downgrade(),
+        system_send.clone(),
+    ));
+    let mailbox = Mailbox::new(
+        mailbox_send,
+        Weak,
+        link.id,
+        link.path.clone(),
+        Arc::new(ExitWatchers::new(linked_exits.clone())),
+        Arc::new(ActorMetrics::default()),
     );
 
-    // create the actor state
+    CreatedChannels {
+        inbox: ActorInbox {
+            msg_recv,
+            mailbox,
+            subord_end_signal_send: sub_end_send,
+            system_send,
+            linked_exits,
+            kill_signal: kil_sig_send.downgrade(),
+        },
+        kill_signal_send: kil_sig_send,
+        stop_send,
+    }
+}
+
+/// Set up the internal concurrency mechanism for an actor.
+pub fn create_actor<Act: Actor>(
+    user_state: Act,
+    link: SupervisorLink<Act>,
+) -> CreatedActor<Act> {
+    let channels = create_channels(&link);
+    let mailbox = channels.inbox.mailbox.clone();
     let state = create_actor_using_mailbox(
         user_state,
-        msg_recv,
-        end_signal_send,
-        sub_end_send,
+        channels.inbox,
+        link,
     );
-    
-    // return
-    (state, mailbox_send, kil_sig_send)
+
+    CreatedActor {
+        state,
+        mailbox,
+        kill_signal_send: channels.kill_signal_send,
+        stop_send: channels.stop_send,
+    }
 }
 
 /// Set up the internal concurrency mechanism for an actor, except its
-/// mailbox, which already exists.
+/// channels, which already exist.
 ///
-/// The mailbox channel receiver is given as a parameter. This returns
-/// the actor state, which, itself, is the dispatch task future.
+/// This returns the actor state, which, itself, is the dispatch task future.
 pub fn create_actor_using_mailbox<Act: Actor>(
     user_state: Act,
-    msg_recv: MsgQueue<Act>,
-    end_signal_send: mpsc::UnboundedSender<Act::End>,
-    subord_end_signal_send: mpsc::UnboundedSender<<Act as Actor>::SubordinateEnd>,
+    inbox: ActorInbox<Act>,
+    link: SupervisorLink<Act>,
 ) -> ActorState<Act> {
+    if let Some(ref system) = link.system {
+        system.track(link.id, link.path.clone(), inbox.kill_signal);
+    }
+
     // create the shared state
     let state_shared = ActorStateShared {
+        id: link.id,
+        path: link.path,
         user_state: UnsafeCell::new(Some(user_state)),
-        access_count: Atomic::new(0),
-        release_mode: Atomic::new(ReleaseMode::Normal),
-        end_signal_send,
-        subord_end_signal_send,
+        access: AccessState::new(),
+        upgrade_task: AtomicTask::new(),
+        end_signal_send: link.end_signal_send,
+        end_sent: Atomic::new(false),
+        structured: link.structured,
+        pending_end: Mutex::new(None),
+        subordinates: Mutex::new(BTreeMap::new()),
+        subord_end_signal_send: inbox.subord_end_signal_send,
+        system_send: inbox.system_send,
+        linked_exits: inbox.linked_exits,
+        mailbox: inbox.mailbox,
+        registry: link.registry,
+        system: link.system,
     };
     let state_shared = Arc::new(state_shared);
 
@@ -75,9 +164,13 @@ pub fn create_actor_using_mailbox<Act: Actor>(
     ActorState {
         shared: state_shared,
         access_status: ActorAccessStatus::Available,
-        msg_recv,
+        msg_recv: inbox.msg_recv,
         curr_msg: None,
+        curr_batch: None,
+        shared_spawner: None,
+        terminated_send: link.terminated_send,
+        stopping_subordinates: None,
     }
 }
 
-// TODO: async creation
\ No newline at end of file
+// TODO: async creation
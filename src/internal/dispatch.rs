@@ -1,10 +1,15 @@
 
 use super::*;
-use crate::msg_union::MailboxEntry;
+use crate::msg_union::{MailboxEntry, MessageTypeUnion, Inspection};
+use crate::manage::ExitReason;
+use crate::manage::dead_letter::{self, DeadLetterReason};
+use crate::spawn::BoxedTask;
+use crate::util::timer;
 
 use std::hint::unreachable_unchecked;
 
 use futures::prelude::*;
+use futures::future;
 use futures::try_ready;
 use futures::task;
 
@@ -15,171 +20,462 @@ impl<Act: Actor> Future for ActorState<Act> {
     type Error = ();
 
     fn poll(&mut self) -> Poll<(), ()> {
-        // if our access count is 0, reset our access status to Available
-        //
-        // for the case that we were notified by a task which freed up the last
-        // actor guard for the current access
-        if self.access_status != ActorAccessStatus::Available {
-            // atomic-acquire that task's writes to user_state
-            //
-            // ****
-            // | it is important that the atomic acquire occurs before potentially
-            // | dropping the future, so that memory barriers are properly instated
-            // | for destruction
-            // ****
-            //
-            if self.shared.access_count.load(Ordering::Acquire) == 0 {
-                trace!("resetting access_status from {:?} to Available", self.access_status);
-                self.access_status = ActorAccessStatus::Available;
+        // whether a message has been processed in this poll
+        let mut processed = false;
+        loop {
+            // under a stepping test executor, process only one message per poll, so that
+            // the test can observe the actor between messages
+            if processed && stepping() {
+                task::current().notify();
+                return Ok(Async::NotReady);
             }
-        }
 
-        // attempt to grab a message to possibly process
-        let msg = {
-            try_ready!(self.populate_msg_cell());
-            self.curr_msg.take()
-        };
+            // once terminating, we are only waiting for subordinates to terminate
+            if self.stopping_subordinates.is_some() {
+                return self.terminate();
+            }
 
-        // exit with completion if the mailbox is empty and dropped
-        let msg = match msg {
-            Some(msg) => msg,
-            None => {
-                trace!("no more messages, actor terminating");
-                return Ok(Async::Ready(()));
-            },
-        };
+            // observe the releases of our actor guards
+            match observe_release(&mut self.access_status, &self.shared.access, &self.shared.path) {
+                Observed::Proceed => (),
+                Observed::Blocked => return Ok(Async::NotReady),
+                Observed::Deleted => {
+                    trace!("{}: actor deleted, terminating routine", self.shared.path);
+                    return self.terminate();
+                },
+            };
 
-        // TODO: this is the point where aforementioned "observing"
-
-        // detect and handle special release modes
-        match self.shared.release_mode.swap(
-            ReleaseMode::Normal,
-            Ordering::Relaxed,
-        ) {
-            ReleaseMode::Normal => (),
-            ReleaseMode::Downgrade => {
-                // downgrade access status from Exclusive to Shared
-                // unless we already downgraded it to Available
-                trace!("downgrading actor guard");
-                if self.access_status == ActorAccessStatus::Exclusive {
-                    self.access_status = ActorAccessStatus::Shared;
+            // process the rest of the current shared batch before taking another message
+            if let Some(batch) = self.curr_batch.take() {
+                match self.continue_batch(batch) {
+                    Ok(()) => processed = true,
+                    Err(blocked) => {
+                        if let Some(poll) = self.shared_blocked(blocked) {
+                            return poll;
+                        }
+                    },
                 }
-            },
-            ReleaseMode::Delete => {
-                // terminate actor routine
-                trace!("actor deleted, terminating routine");
-                return Ok(Async::Ready(()));
-            },
-        };
-
-        // yield as not-ready if the message access type isn't compatible with our
-        // current access status
-        let may_access: bool = {
-            let msg_shared: bool = match msg {
-                MsgQueueEntry::MailboxEntry(MailboxEntry::Shared(_)) => true,
-                MsgQueueEntry::MailboxEntry(MailboxEntry::Mut(_)) => false,
-                MsgQueueEntry::SubordinateEnd(_) => false,
-            };
-            match (msg_shared, self.access_status) {
-                (true, ActorAccessStatus::Available) => true,
-                (true, ActorAccessStatus::Shared) => true,
-                (false, ActorAccessStatus::Available) => true,
-                _ => false,
+                continue;
             }
-        };
-        if !may_access {
-            trace!("message access type is not compatible with actor access status, actor blocked");
-            self.curr_msg = Some(msg);
-            return Ok(Async::NotReady);
-        }
 
-        // launch a task to process the message
-        // this code manually implements synchronization, so should be entirely considered unsafe
-
-        fn acquire_guard_shared<Act>(actor: &mut ActorState<Act>) -> ActorGuardShared<Act>
-        where
-            Act: Actor
-        {
-            trace!("beginning shared actor access");
-
-            let shared_state = Arc::clone(&actor.shared);
-            shared_state.access_count.fetch_add(1, Ordering::Relaxed);
-            let dispatch_task = task::current();
-
-            actor.access_status = ActorAccessStatus::Shared;
-            // create a shared alias into the user_state unsafe cell
-            let ptr: *const Act  = unsafe {
-                let ptr: &Option<Act> = &*shared_state.user_state.get();
-                match ptr {
-                    &Some(ref act) => act as *const Act,
-                    &None => unreachable_unchecked()
-                }
-            };
-            let actor_guard = ActorGuardShared {
-                shared_state,
-                dispatch_task,
-                ptr,
+            // attempt to grab a message to possibly process
+            let msg = {
+                try_ready!(self.populate_msg_cell());
+                self.curr_msg.take()
             };
 
-            actor_guard
-        }
-
-        fn acquire_guard_mut<Act>(actor: &mut ActorState<Act>) -> ActorGuardMut<Act>
-            where
-                Act: Actor
-        {
-            trace!("beginning exclusive actor access");
-
-            let shared_state = Arc::clone(&actor.shared);
-            let previous_access_count = shared_state.access_count.swap(1, Ordering::Relaxed);
-            debug_assert_eq!(previous_access_count, 0);
-            let dispatch_task = task::current();
-
-            actor.access_status = ActorAccessStatus::Exclusive;
-            // create a unique alias into the user_state unsafe cell
-            let ptr: *mut Act  = unsafe {
-                let ptr: &mut Option<Act> = &mut *shared_state.user_state.get();
-                match ptr {
-                    &mut Some(ref mut act) => act as *mut Act,
-                    &mut None => unreachable_unchecked()
-                }
+            // exit with completion if the mailbox is empty and dropped
+            let msg = match msg {
+                Some(msg) => msg,
+                None => {
+                    if let Some(end) = self.msg_recv.take_stop_reason() {
+                        trace!("{}: actor was stopped by its supervisor", self.shared.path);
+                        self.shared.send_end(end);
+                    }
+                    trace!("{}: no more messages, actor terminating", self.shared.path);
+                    return self.terminate();
+                },
             };
-            let actor_guard = ActorGuardMut {
-                shared_state,
-                dispatch_task,
-                ptr,
+
+            // discard the message if it expired while queued
+            let msg = match msg {
+                MsgQueueEntry::MailboxEntry(entry, Some(expiry)) if timer::now() >= expiry => {
+                    self.shared.discard_expired(entry);
+                    continue;
+                },
+                msg => msg,
             };
 
-            actor_guard
-        }
+            // yield as not-ready if the message access type isn't compatible with our
+            // current access status
+            if !may_access(msg.is_shared(), self.access_status) {
+                trace!("{}: message access type is not compatible with actor access status, actor blocked", self.shared.path);
+                self.curr_msg = Some(msg);
+                return Ok(Async::NotReady);
+            }
 
-        // update synchronization state, and create an actor guard
-        // then pass the guard and message to user code
-        match msg {
-            MsgQueueEntry::MailboxEntry(msg) => match msg {
-                MailboxEntry::Shared(msg_vec) => for msg in msg_vec {
-                    let guard = acquire_guard_shared(self);
-                    Act::handle_msg_shared(guard, msg);
+            // launch a task to process the message
+            // this code manually implements synchronization, so should be entirely considered unsafe
+
+            // update synchronization state, and create an actor guard
+            // then pass the guard and message to user code
+            match msg {
+                MsgQueueEntry::MailboxEntry(msg, _) => match msg {
+                    MailboxEntry::Shared(msg_vec) => {
+                        // the batch is processed one message at a time, so that the
+                        // upgrade of one of its guards is observed before the next
+                        // message begins
+                        if !msg_vec.is_empty() {
+                            self.curr_batch = Some(msg_vec.into_iter());
+                        }
+                        continue;
+                    },
+                    MailboxEntry::Mut(msg) => {
+                        let guard = acquire_guard_mut(self);
+                        Act::handle_msg_mut(guard, msg);
+                    },
                 },
-                MailboxEntry::Mut(msg) => {
+                MsgQueueEntry::SubordinateEnd(id, msg) => {
+                    self.shared.untrack_subordinate(id);
                     let guard = acquire_guard_mut(self);
-                    Act::handle_msg_mut(guard, msg);
+                    Act::handle_subordinate_end(guard, msg);
                 },
-            },
-            MsgQueueEntry::SubordinateEnd(msg) => {
-                let guard = acquire_guard_mut(self);
-                Act::handle_subordinate_end(guard, msg);
-            },
-        };
+                MsgQueueEntry::Inspect(inspection) => {
+                    let guard = match acquire_guard_shared(self) {
+                        Ok(guard) => guard,
+                        Err(blocked) => {
+                            self.curr_msg = Some(MsgQueueEntry::Inspect(inspection));
+                            match self.shared_blocked(blocked) {
+                                Some(poll) => return poll,
+                                None => continue,
+                            }
+                        },
+                    };
+
+                    // the mailbox is not generic over the actor type, so the inspection
+                    // may be for an actor type which shares our message type union
+                    match inspection.downcast::<Inspection<Act>>() {
+                        Ok(inspection) => inspection(guard),
+                        Err(_) => trace!("{}: inspection is for a different actor type, discarding", self.shared.path),
+                    };
+                },
+            };
+
+            processed = true;
+
+            // loop until we terminate or block
+            // if the actor processed the message synchronously, this actor may already be released
+        }
+    }
+}
+
+/// If the actor routine is dropped without terminating, such as due to a panic
+/// in a message handler, it still exits.
+impl<Act: Actor> Drop for ActorState<Act> {
+    fn drop(&mut self) {
+        // the mailbox can only be drained from within a task
+        if task::is_in_task() {
+            self.discard_queued();
+        }
+        self.shared.exit(ExitReason::Dropped);
+    }
+}
+
+/// The memory through which actor guards convey their releases to the actor routine.
+///
+/// This is atomic for an `Actor`, and made of plain cells for a `LocalActor`, but
+/// both of their routines observe it in the same way.
+pub(crate) trait AccessSync {
+    /// Load the access count, acquiring the writes to the user state of the guards
+    /// which lowered it.
+    fn load_access_count(&self) -> u32;
+
+    /// Replace the release mode, returning the previous one.
+    fn swap_release_mode(&self, mode: ReleaseMode) -> ReleaseMode;
+}
+
+/// Outcome of the actor routine observing the releases of its guards.
+pub(crate) enum Observed {
+    /// The routine may go on to process messages.
+    Proceed,
+    /// A deleting guard has not yet released, and will notify the routine when it does.
+    Blocked,
+    /// The actor has been deleted, so the routine should terminate.
+    Deleted,
+}
+
+/// Update an actor routine's access status, according to the releases of its guards.
+pub(crate) fn observe_release<S: AccessSync>(
+    access_status: &mut ActorAccessStatus,
+    sync: &S,
+    path: &ActorPath,
+) -> Observed {
+    // if our access count is 0, reset our access status to Available
+    //
+    // for the case that we were notified by a task which freed up the last
+    // actor guard for the current access
+    if *access_status != ActorAccessStatus::Available {
+        // atomic-acquire that task's writes to user_state
+        //
+        // ****
+        // | it is important that the atomic acquire occurs before potentially
+        // | dropping the future, so that memory barriers are properly instated
+        // | for destruction
+        // ****
+        //
+        if sync.load_access_count() == 0 {
+            trace!("{}: resetting access_status from {:?} to Available", path, access_status);
+            *access_status = ActorAccessStatus::Available;
+        }
+    }
+
+    // detect and handle special release modes
+    //
+    // this is the point where the release mode is "observed", which happens
+    // before waiting for messages, so that a deletion is acted upon even if no
+    // further messages arrive
+    match sync.swap_release_mode(ReleaseMode::Normal) {
+        ReleaseMode::Normal => Observed::Proceed,
+        ReleaseMode::Downgrade => {
+            // downgrade access status from Exclusive to Shared
+            // unless we already downgraded it to Available
+            trace!("{}: downgrading actor guard", path);
+            if *access_status == ActorAccessStatus::Exclusive {
+                *access_status = ActorAccessStatus::Shared;
+            }
+            Observed::Proceed
+        },
+        ReleaseMode::Delete if *access_status != ActorAccessStatus::Available => {
+            // the deleting guard has not released yet, so we haven't acquired
+            // its writes, and we'll be notified when it does release
+            sync.swap_release_mode(ReleaseMode::Delete);
+            Observed::Blocked
+        },
+        ReleaseMode::Delete => Observed::Deleted,
+    }
+}
+
+/// Whether actor routines should process only one message each time they are
+/// polled, because they are being stepped by a test executor.
+#[cfg(any(test, feature = "testing"))]
+fn stepping() -> bool {
+    crate::testing::executor::stepping()
+}
 
-        // recurse until we terminate or block
-        // if the actor processed the message synchronously, this actor may already be released
-        self.poll()
+/// Without the test harness, actor routines are never stepped.
+#[cfg(not(any(test, feature = "testing")))]
+#[inline(always)]
+fn stepping() -> bool {
+    false
+}
+
+/// Whether a message may be processed in the given access status, depending on
+/// whether it is processed with shared access.
+pub(crate) fn may_access(shared: bool, access_status: ActorAccessStatus) -> bool {
+    matches!(
+        (shared, access_status),
+        (true, ActorAccessStatus::Available)
+        | (true, ActorAccessStatus::Shared)
+        | (false, ActorAccessStatus::Available)
+    )
+}
+
+/// Why the actor routine cannot begin another shared access, given the current
+/// access count, if it cannot.
+pub(crate) fn shared_blocked_by(
+    access_count: u32,
+    access_status: ActorAccessStatus,
+) -> Option<SharedBlocked> {
+    if access_count & !ACCESS_COUNT_MASK != 0 {
+        Some(SharedBlocked::Upgrade)
+    } else if access_count == 0 && access_status == ActorAccessStatus::Shared {
+        Some(SharedBlocked::Stale)
+    } else {
+        None
+    }
+}
+
+/// Reason why the actor routine could not begin shared access.
+pub(crate) enum SharedBlocked {
+    /// A shared guard has upgraded, or is waiting to upgrade.
+    Upgrade,
+    /// Every guard of the current shared access has released, so the release mode
+    /// must be observed again.
+    Stale,
+}
+
+fn acquire_guard_shared<Act>(
+    actor: &mut ActorState<Act>,
+) -> Result<ActorGuardShared<Act>, SharedBlocked>
+where
+    Act: Actor
+{
+    actor.shared.access.begin_shared(actor.access_status)?;
+
+    trace!("{}: beginning shared actor access", actor.shared.path);
+
+    let shared_state = Arc::clone(&actor.shared);
+    let dispatch_task = task::current();
+
+    actor.access_status = ActorAccessStatus::Shared;
+    // create a shared alias into the user_state unsafe cell
+    let ptr: *const Act  = unsafe {
+        let ptr: &Option<Act> = &*shared_state.user_state.get();
+        match *ptr {
+            Some(ref act) => act as *const Act,
+            None => unreachable_unchecked()
+        }
+    };
+    Ok(ActorGuardShared {
+        shared_state,
+        dispatch_task,
+        ptr,
+    })
+}
+
+fn acquire_guard_mut<Act>(actor: &mut ActorState<Act>) -> ActorGuardMut<Act>
+    where
+        Act: Actor
+{
+    trace!("{}: beginning exclusive actor access", actor.shared.path);
+
+    let shared_state = Arc::clone(&actor.shared);
+    shared_state.access.begin_mut();
+    let dispatch_task = task::current();
+
+    actor.access_status = ActorAccessStatus::Exclusive;
+    // create a unique alias into the user_state unsafe cell
+    let ptr: *mut Act  = unsafe {
+        let ptr: &mut Option<Act> = &mut *shared_state.user_state.get();
+        match *ptr {
+            Some(ref mut act) => act as *mut Act,
+            None => unreachable_unchecked()
+        }
+    };
+    ActorGuardMut {
+        shared_state,
+        dispatch_task,
+        ptr,
+    }
+}
+
+/// Process a shared message as its own task on the shared spawner.
+///
+/// The guard is moved into the task, so the actor's access count stays raised
+/// until the handler completes. If the spawner refuses the task, the message
+/// is processed on the dispatch task instead.
+fn spawn_msg_shared<Act: Actor>(
+    spawner: &SharedSpawner,
+    path: &ActorPath,
+    guard: ActorGuardShared<Act>,
+    msg: <Act::Message as MessageTypeUnion>::SharedUnion,
+) {
+    let handler: BoxedTask = Box::new(future::lazy(move || {
+        Act::handle_msg_shared(guard, msg);
+        Ok(())
+    }));
+    if let Err(mut handler) = spawner.spawn(handler) {
+        trace!("{}: shared spawner refused handler, processing on dispatch task", path);
+        let _ = handler.poll();
+    }
+}
+
+impl<Act: Actor> ActorStateShared<Act> {
+    /// Discard a mailbox entry which expired before it could be processed.
+    fn discard_expired(&self, entry: MailboxEntry<Act::Message>) {
+        let count = match entry {
+            MailboxEntry::Shared(ref msg_vec) => msg_vec.len(),
+            MailboxEntry::Mut(_) => 1,
+        };
+        trace!("{}: discarding {} expired messages", self.path, count);
+        self.mailbox.metrics.record_expired(count as u64);
+        dead_letter::post(self.id, &self.path, DeadLetterReason::Expired, entry);
     }
 }
 
 // helper functions to be called from <ActorState as Future>::poll
 impl<Act: Actor> ActorState<Act> {
+    /// Fan the messages of shared batches out onto the given spawner, rather
+    /// than processing them serially on the dispatch task.
+    pub fn set_shared_spawner(&mut self, spawner: SharedSpawner) {
+        self.shared_spawner = Some(spawner);
+    }
+
+    /// Terminate the actor routine.
+    ///
+    /// In structured mode, this first stops all subordinates, and yields until they
+    /// have all terminated.
+    fn terminate(&mut self) -> Poll<(), ()> {
+        self.discard_queued();
+
+        if self.shared.structured {
+            if self.stopping_subordinates.is_none() {
+                trace!("{}: stopping subordinates of structured actor", self.shared.path);
+                self.stopping_subordinates = Some(self.shared.kill_subordinates());
+            }
+            if let Some(ref mut subordinates) = self.stopping_subordinates {
+                subordinates.retain_mut(|terminated_recv| match terminated_recv.poll() {
+                    Ok(Async::Ready(())) => false,
+                    Ok(Async::NotReady) => true,
+                    Err(never) => match never {},
+                });
+                if !subordinates.is_empty() {
+                    trace!("{}: waiting for {} subordinates to terminate", self.shared.path, subordinates.len());
+                    return Ok(Async::NotReady);
+                }
+            }
+            self.shared.flush_pending_end();
+        }
+
+        let reason = if self.shared.end_sent.load(Ordering::Relaxed) {
+            ExitReason::Ended
+        } else {
+            ExitReason::Killed
+        };
+        self.shared.exit(reason);
+        self.terminated_send.send();
+        Ok(Async::Ready(()))
+    }
+
+    /// Close the mailbox, and post the messages which are still queued as dead
+    /// letters.
+    fn discard_queued(&mut self) {
+        if let Some(batch) = self.curr_batch.take() {
+            let entry = MailboxEntry::<Act::Message>::Shared(batch.collect());
+            dead_letter::post(self.shared.id, &self.shared.path, DeadLetterReason::Terminated, entry);
+        }
+        if let Some(MsgQueueEntry::MailboxEntry(entry, _)) = self.curr_msg.take() {
+            dead_letter::post(self.shared.id, &self.shared.path, DeadLetterReason::Terminated, entry);
+        }
+        for envelope in self.msg_recv.drain_mailbox() {
+            dead_letter::post_envelope(self.shared.id, &self.shared.path, DeadLetterReason::Terminated, envelope);
+        }
+    }
+
+    /// Begin the next message of the current shared batch, unless the actor is
+    /// blocked, in which case the batch is put back.
+    fn continue_batch(&mut self, mut batch: SharedBatch<Act>) -> Result<(), SharedBlocked> {
+        let guard = match acquire_guard_shared(self) {
+            Ok(guard) => guard,
+            Err(blocked) => {
+                self.curr_batch = Some(batch);
+                return Err(blocked);
+            },
+        };
+
+        // batches are never left empty
+        let msg = match batch.next() {
+            Some(msg) => msg,
+            None => unreachable!("empty shared batch"),
+        };
+        if batch.len() > 0 {
+            self.curr_batch = Some(batch);
+        }
+
+        match self.shared_spawner {
+            Some(ref spawner) => spawn_msg_shared(spawner, &self.shared.path, guard, msg),
+            None => Act::handle_msg_shared(guard, msg),
+        };
+        Ok(())
+    }
+
+    /// Determine how to proceed after a shared message, which was put back, could not
+    /// begin shared access.
+    ///
+    /// Returns `None` if the dispatch loop should continue, to observe the releases
+    /// of our actor guards again.
+    fn shared_blocked(&self, blocked: SharedBlocked) -> Option<Poll<(), ()>> {
+        match blocked {
+            SharedBlocked::Upgrade => {
+                // the upgrading guard notifies us once it releases, or once it abandons
+                // the upgrade, so that shared access resumes
+                trace!("{}: shared actor guard is upgrading, actor blocked", self.shared.path);
+                Some(Ok(Async::NotReady))
+            },
+            SharedBlocked::Stale => None,
+        }
+    }
+
     /// If `curr_msg` is empty, poll to fill it with a message from `msg_recv`.
     ///
     /// This will only leave `curr_msg` as `None` if `msg_recv` has terminated,
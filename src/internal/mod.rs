@@ -3,16 +3,25 @@
 #[cfg(test)]
 mod test;
 
-use self::queue::{MsgQueue, MsgQueueEntry};
+use self::queue::{MsgQueue, MsgQueueEntry, SystemSend};
+use self::supervise::{ChildLink, EndSignalSend};
+use self::watch::LinkedExits;
+use self::access::AccessState;
+use crate::manage::{ActorId, ActorPath, Registry, ActorSystem};
+use crate::mailbox::MailboxWeak;
+use crate::util::drop_signal::{DropSignalSend, DropSignalRecv};
 use crate::Actor;
-use crate::msg_union::ActorMailboxEntry;
+use crate::spawn::Spawner;
+use crate::msg_union::{ActorMailboxEntry, MessageTypeUnion};
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
 use std::cell::UnsafeCell;
 
 use atomic::{Atomic, Ordering};
+use futures::Future;
 use futures::sync::mpsc;
-use futures::task::Task;
+use futures::task::{Task, AtomicTask};
 
 /// Actor dispatch task.
 pub mod dispatch;
@@ -29,6 +38,12 @@ pub mod supervise;
 /// Abstraction over message queues.
 pub mod queue;
 
+/// Notification of actors watching for an actor's termination.
+pub mod watch;
+
+/// The atomic memory of the actor access protocol.
+pub mod access;
+
 /// Reflex's state for an actor which is owned by the actor's dispatch routine.
 pub struct ActorState<Act: Actor> {
     // handle to the shared state
@@ -39,29 +54,91 @@ pub struct ActorState<Act: Actor> {
     // the message queue, and the slot for pushing a message back in
     msg_recv: MsgQueue<Act>,
     curr_msg: Option<MsgQueueEntry<Act>>,
+    // the messages of the shared batch being processed which have yet to begin
+    curr_batch: Option<SharedBatch<Act>>,
+
+    // if present, the messages of shared batches are processed as separate tasks
+    shared_spawner: Option<SharedSpawner>,
+
+    // signals the supervisor once this routine terminates
+    terminated_send: DropSignalSend,
+    // in structured mode, once the actor is terminating, the termination signals
+    // of the subordinates which we are waiting for
+    stopping_subordinates: Option<Vec<DropSignalRecv>>,
 }
 
+/// A spawner, shared by the actors which spawn tasks onto it.
+pub type SharedSpawner = Arc<dyn Spawner>;
+
+/// The remaining messages of a shared batch, which are processed one at a time.
+pub type SharedBatch<Act> = smallvec::IntoIter<[<<Act as Actor>::Message as MessageTypeUnion>::SharedUnion; 4]>;
+
 
 /// Reflex's state for an actor which is reference counted.
 pub struct ActorStateShared<Act: Actor> {
+    // this actor's unique id
+    id: ActorId,
+    // this actor's hierarchical path
+    path: ActorPath,
+
     // the user's actor struct, which we manually synchronize
     // additionally, we make unsafe assumptions on when this is the Some variant
     user_state: UnsafeCell<Option<Act>>,
 
-    // the current number of guards accessing (mutably or immutably) the user state
-    access_count: Atomic<u32>,
-    // atomic memory for an actor guard to tell the internal actor procedure that
-    // the guard is releasing in some relevant non-default way
-    release_mode: Atomic<ReleaseMode>,
+    // the access count and release mode, through which guards synchronize with
+    // the actor routine
+    access: AccessState,
+    // the task of a shared guard which is waiting to upgrade, if any
+    upgrade_task: AtomicTask,
 
     // channel to notify manager actor of explicit termination
-    end_signal_send: mpsc::UnboundedSender<<Act as Actor>::End>,
+    end_signal_send: EndSignalSend<<Act as Actor>::End>,
+    // whether an end value has already been sent through `end_signal_send`
+    end_sent: Atomic<bool>,
+
+    // whether this actor's subordinates are stopped when it terminates, before it
+    // sends its end value, which is inherited by its subordinates
+    structured: bool,
+    // in structured mode, the end value held back until subordinates terminate
+    pending_end: Mutex<Option<<Act as Actor>::End>>,
+    // links to this actor's live subordinates
+    subordinates: Mutex<BTreeMap<ActorId, ChildLink>>,
 
     // the `end_signal_send` field to be cloned out to subordinates
     // this queue connects to this own actor's MsgQueue
-    subord_end_signal_send: mpsc::UnboundedSender<<Act as Actor>::SubordinateEnd>,
+    subord_end_signal_send: EndSignalSend<<Act as Actor>::SubordinateEnd>,
+    // the sender of this actor's system message channel
+    system_send: SystemSend<Act>,
+    // this actor's end of its links
+    linked_exits: Arc<LinkedExits<Act>>,
+
+    // a mailbox to this actor, which does not keep it alive
+    mailbox: MailboxWeak<<Act as Actor>::Message>,
+    // the registry of this actor's tree, from which it unregisters upon termination
+    registry: Registry,
+    // the actor system which this actor's tree belongs to, if any
+    system: Option<ActorSystem>,
 }
 
+/// Flag bit of `access_count`, set while the only shared guard has upgraded itself
+/// to an `ActorGuardMut`.
+///
+/// If the actor routine observes this flag when it attempts to begin shared access,
+/// it should not begin it, but it remains in the `Shared` access status. The upgraded
+/// guard will notify the actor routine when it downgrades or releases.
+pub const UPGRADED: u32 = 1 << 31;
+
+/// Flag bit of `access_count`, set while a shared guard is waiting for the other
+/// shared guards to release, so that it can upgrade.
+///
+/// The actor routine should treat this like `UPGRADED`, so that new shared accesses
+/// do not starve the waiting guard. If the guard abandons the upgrade, it notifies
+/// the actor routine.
+pub const UPGRADE_PENDING: u32 = 1 << 30;
+
+/// Mask of the bits of `access_count` which actually count guards.
+pub const ACCESS_COUNT_MASK: u32 = !(UPGRADED | UPGRADE_PENDING);
+
 /// The way in which an actor is currently being accessed, equivalent to the state of a
 /// read/write lock.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -82,12 +159,13 @@ pub enum ActorAccessStatus {
 ///
 /// TODO: make a note here on the non-obvious semantics of what it means for the
 ///       actor routine to "observe" this
+#[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ReleaseMode {
     /// There is nothing notable about the release mode.
     ///
     /// If the actor routine observes:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Normal
     /// access_count = 0
     /// ```
@@ -98,7 +176,7 @@ pub enum ReleaseMode {
     /// other threads to concurrently read.
     ///
     /// If the actor routine observes:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Downgrade
     /// access_count = 1
     /// ```
@@ -106,7 +184,7 @@ pub enum ReleaseMode {
     /// *unlocked* state.
     ///
     /// If the actor routine observes:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Downgrade
     /// access_count = 0
     /// ```
@@ -116,15 +194,18 @@ pub enum ReleaseMode {
     /// to reset the release mode to `Normal`).
     ///
     /// It is **invalid** for the actor routine to observe:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Downgrade
     /// access_count > 1
     /// ```
+    /// unless its access status is already `Shared`. This can occur when a guard
+    /// which was upgraded from shared access downgrades again before the actor
+    /// routine observed the `UPGRADED` flag, in which case there is nothing to do.
     Downgrade,
     /// An `ActorGuardMut` is deleting the actor.
     ///
     /// If the actor routine observes:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Delete
     /// access_count = 0
     /// ```
@@ -138,7 +219,7 @@ pub enum ReleaseMode {
     /// routine not to observe `user_state` in the `Some` variant.
     ///
     /// It is **invalid** for the actor routine to observe:
-    /// ```no_run,noplaypen
+    /// ```text
     /// release_mode = Delete
     /// access_count != 0
     /// ```
@@ -158,6 +239,19 @@ pub struct ActorGuardShared<Act: Actor> {
     ptr: *const Act,
 }
 
+/// Future for upgrading an `ActorGuardShared` to an `ActorGuardMut`.
+///
+/// Resolves once every other shared guard has released. While this is pending,
+/// the actor will not begin new shared accesses. If another guard is already
+/// waiting to upgrade, this fails with the original guard, because waiting would
+/// deadlock.
+#[must_use = "futures do nothing unless polled"]
+pub struct ActorGuardUpgrade<Act: Actor> {
+    guard: Option<ActorGuardShared<Act>>,
+    // whether we have set the UPGRADE_PENDING flag
+    pending: bool,
+}
+
 /// Synchronization guard for exclusive (mutable) access to an actor.
 ///
 /// This type is notably `'static`.
@@ -171,10 +265,36 @@ pub struct ActorGuardMut<Act: Actor> {
     ptr: *mut Act,
 }
 
+/// Shared actor guard which has been projected into some part of the actor's state.
+///
+/// This keeps the actor accessed in the same way as the `ActorGuardShared` it was
+/// created from, and is likewise `'static`, and clone-shareable.
+pub struct MappedActorGuardShared<Act: Actor, T: ?Sized> {
+    // the original guard, which holds the access
+    guard: ActorGuardShared<Act>,
+    // pointer into the actor's state
+    ptr: *const T,
+}
+
+/// Mutable actor guard which has been projected into some part of the actor's state.
+///
+/// This keeps the actor accessed in the same way as the `ActorGuardMut` it was
+/// created from, and is likewise `'static`.
+pub struct MappedActorGuardMut<Act: Actor, T: ?Sized> {
+    // the original guard, which holds the access
+    guard: ActorGuardMut<Act>,
+    // pointer into the actor's state
+    ptr: *mut T,
+}
+
 unsafe impl<Act: Send + Actor> Send for ActorGuardShared<Act> {}
 unsafe impl<Act: Sync + Actor> Sync for ActorGuardShared<Act> {}
 unsafe impl<Act: Send + Actor> Send for ActorGuardMut<Act> {}
 unsafe impl<Act: Sync + Actor> Sync for ActorGuardMut<Act> {}
+unsafe impl<Act: Actor, T: ?Sized + Sync> Send for MappedActorGuardShared<Act, T> {}
+unsafe impl<Act: Actor, T: ?Sized + Sync> Sync for MappedActorGuardShared<Act, T> {}
+unsafe impl<Act: Actor, T: ?Sized + Send> Send for MappedActorGuardMut<Act, T> {}
+unsafe impl<Act: Actor, T: ?Sized + Sync> Sync for MappedActorGuardMut<Act, T> {}
 
 
 unsafe impl<Act> Send for ActorState<Act>
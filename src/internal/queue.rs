@@ -1,16 +1,28 @@
 
 use crate::{
     Actor,
-    msg_union::MailboxEntry,
+    msg_union::{MailboxEntry, Envelope, ActorEnvelope},
     util::drop_signal::DropSignalRecv,
+    manage::ActorId,
+    internal::supervise::EndSignalRecv,
 };
 
+use std::any::Any;
+use std::time::Instant;
+
 use futures::{
     Stream, Future, Poll, Async,
-    sync::mpsc,
+    sync::{mpsc, oneshot},
     stream::Fuse,
 };
 
+/// Unbounded channel through which reflex itself sends messages to an actor,
+/// such as to notify it of another actor's termination.
+pub type SystemSend<Act> = mpsc::UnboundedSender<MsgQueueEntry<Act>>;
+
+/// Receiving end of `SystemSend`.
+pub type SystemRecv<Act> = mpsc::UnboundedReceiver<MsgQueueEntry<Act>>;
+
 /// Abstraction over actor message queues.
 ///
 /// Not round-robin; prioritizes queues over other queues, in a deliberate order.
@@ -19,34 +31,79 @@ use futures::{
 ///
 /// Instead of actually emitting an element upon receiving the drop signal, the queue
 /// simply terminates. This may change in the future, to facilitate drop recovery.
+/// If the actor was stopped with an end value, that value is sent before the drop
+/// signal, and can be taken from the queue after it terminates.
 pub struct MsgQueue<Act: Actor> {
     kil_sig_recv: DropSignalRecv,
-    sub_end_recv: Fuse<mpsc::UnboundedReceiver<
-        <Act as Actor>::SubordinateEnd
-    >>,
-    mailbox_recv: Fuse<mpsc::Receiver<
-        MailboxEntry<<Act as Actor>::Message>
-    >>,
+    stop_recv: oneshot::Receiver<<Act as Actor>::End>,
+    sub_end_recv: Fuse<EndSignalRecv<<Act as Actor>::SubordinateEnd>>,
+    system_recv: Fuse<SystemRecv<Act>>,
+    mailbox_recv: Fuse<mpsc::Receiver<ActorEnvelope<Act>>>,
 }
 
 /// Element type of `MsgQueue`.
 pub enum MsgQueueEntry<Act: Actor> {
-    MailboxEntry(MailboxEntry<<Act as Actor>::Message>),
-    SubordinateEnd(<Act as Actor>::SubordinateEnd),
+    /// A mailbox entry, which is discarded if it is not processed before its
+    /// expiry, if any.
+    MailboxEntry(MailboxEntry<<Act as Actor>::Message>, Option<Instant>),
+    SubordinateEnd(ActorId, <Act as Actor>::SubordinateEnd),
+    /// A boxed `Inspection<Act>`.
+    Inspect(Box<dyn Any + Send>),
+}
+
+impl<Act: Actor> MsgQueueEntry<Act> {
+    /// Whether this entry is processed with shared access.
+    pub fn is_shared(&self) -> bool {
+        match *self {
+            MsgQueueEntry::MailboxEntry(MailboxEntry::Shared(_), _) => true,
+            MsgQueueEntry::MailboxEntry(MailboxEntry::Mut(_), _) => false,
+            MsgQueueEntry::SubordinateEnd(..) => false,
+            MsgQueueEntry::Inspect(_) => true,
+        }
+    }
+
+    fn from_envelope(envelope: ActorEnvelope<Act>) -> Self {
+        match envelope {
+            Envelope::Entry(entry) => MsgQueueEntry::MailboxEntry(entry, None),
+            Envelope::Expiring(entry, expiry) => MsgQueueEntry::MailboxEntry(entry, Some(expiry)),
+            Envelope::Inspect(inspection) => MsgQueueEntry::Inspect(inspection),
+        }
+    }
 }
 
 impl<Act: Actor> MsgQueue<Act> {
     pub fn new(
         kil_sig_recv: DropSignalRecv,
-        mailbox_recv: mpsc::Receiver<MailboxEntry<<Act as Actor>::Message>>,
-        sub_end_recv: mpsc::UnboundedReceiver<<Act as Actor>::SubordinateEnd>,
+        stop_recv: oneshot::Receiver<<Act as Actor>::End>,
+        mailbox_recv: mpsc::Receiver<ActorEnvelope<Act>>,
+        sub_end_recv: EndSignalRecv<<Act as Actor>::SubordinateEnd>,
+        system_recv: SystemRecv<Act>,
     ) -> Self {
         MsgQueue {
             kil_sig_recv,
+            stop_recv,
             mailbox_recv: mailbox_recv.fuse(),
             sub_end_recv: sub_end_recv.fuse(),
+            system_recv: system_recv.fuse(),
         }
     }
+
+    /// Close the mailbox, and take the entries which are still queued in it.
+    ///
+    /// This must be called from within the actor's task.
+    pub fn drain_mailbox(&mut self) -> Vec<ActorEnvelope<Act>> {
+        self.mailbox_recv.get_mut().close();
+        let mut drained = Vec::new();
+        while let Ok(Async::Ready(Some(envelope))) = self.mailbox_recv.poll() {
+            drained.push(envelope);
+        }
+        drained
+    }
+
+    /// Take the end value which the actor was stopped with, if any.
+    pub fn take_stop_reason(&mut self) -> Option<<Act as Actor>::End> {
+        self.stop_recv.try_recv().ok().and_then(|reason| reason)
+    }
 }
 
 impl<Act: Actor> Stream for MsgQueue<Act> {
@@ -77,11 +134,19 @@ impl<Act: Actor> Stream for MsgQueue<Act> {
         }
 
         self.sub_end_recv.poll()
-            .map(async_flatten(MsgQueueEntry::SubordinateEnd, &mut blocked))
+            .map(async_flatten(
+                |(id, end)| MsgQueueEntry::SubordinateEnd(id, end),
+                &mut blocked,
+            ))
             .transpose()
+            .or_else(|| self.system_recv
+                .poll()
+                .map(async_flatten(|entry| entry, &mut blocked))
+                .transpose()
+            )
             .or_else(|| self.mailbox_recv
                 .poll()
-                .map(async_flatten(MsgQueueEntry::MailboxEntry, &mut blocked))
+                .map(async_flatten(MsgQueueEntry::from_envelope, &mut blocked))
                 .transpose()
             )
             .transpose()
@@ -1,82 +1,401 @@
 
 use crate::Actor;
 use super::ActorStateShared;
-use crate::manage::{SubordinateActor, ActorSocket};
-use crate::mailbox::MailboxOwned;
-use super::create::create_actor;
-use super::queue::MsgQueue;
+use crate::manage::{
+    SubordinateActor,
+    SubordinateHandle,
+    ActorSocket,
+    ActorId,
+    ActorPath,
+    ChildInfo,
+    Registry,
+    ExitReason,
+    ActorSystem,
+};
+use crate::mailbox::{MailboxOwned, generic::Mailbox, ownership::Ownership};
+use crate::msg_union::{MessageTypeUnion, MailboxEntry};
+use crate::spawn::{Spawner, blocking::DedicatedThreads};
+use super::queue::MsgQueueEntry;
+use super::watch::Watcher;
+use super::create::{create_actor, create_channels};
 use futures::sync::mpsc;
-use crate::util::drop_signal::drop_signal_channel;
+use crate::util::lock;
+use crate::util::drop_signal::{
+    DropSignalSend,
+    DropSignalRecv,
+    DropSignalArcWeak,
+    drop_signal_channel,
+};
 
+use std::mem;
+use std::collections::BTreeMap;
+
+use atomic::Ordering;
+
+/// Channel through which actors send their end value, tagged with their id.
+pub type EndSignalSend<End> = mpsc::UnboundedSender<(ActorId, End)>;
+
+/// Receiving end of `EndSignalSend`.
+pub type EndSignalRecv<End> = mpsc::UnboundedReceiver<(ActorId, End)>;
+
+/// An actor's connection to its supervisor, which is given to the actor upon
+/// its creation.
+pub struct SupervisorLink<Act: Actor> {
+    /// The actor's id.
+    pub id: ActorId,
+    /// The actor's path.
+    pub path: ActorPath,
+    /// Channel to notify the supervisor of explicit termination.
+    pub end_signal_send: EndSignalSend<Act::End>,
+    /// Whether the actor runs in structured mode.
+    pub structured: bool,
+    /// Signals the supervisor once the actor routine terminates.
+    pub terminated_send: DropSignalSend,
+    /// The registry of the actor's tree.
+    pub registry: Registry,
+    /// The actor system which the actor's tree belongs to, if any.
+    pub system: Option<ActorSystem>,
+}
+
+impl<Act: Actor> SupervisorLink<Act> {
+    /// Create the link for an actor without a supervisor.
+    pub fn root(
+        end_signal_send: EndSignalSend<Act::End>,
+        structured: bool,
+        registry: Registry,
+        system: Option<ActorSystem>,
+    ) -> Self {
+        let (terminated_send, _) = drop_signal_channel();
+        SupervisorLink {
+            id: ActorId::next(),
+            path: ActorPath::root(),
+            end_signal_send,
+            structured,
+            terminated_send,
+            registry,
+            system,
+        }
+    }
+}
+
+/// A supervisor's link to one of its subordinates.
+pub struct ChildLink {
+    info: ChildInfo,
+    kill_signal: DropSignalArcWeak,
+    terminated_recv: DropSignalRecv,
+}
+
+impl<Act: Actor> ActorStateShared<Act> {
+    /// Send this actor's end value to its manager.
+    ///
+    /// Only the first end value is sent, since an actor may be explicitly deleted
+    /// and stopped by its supervisor concurrently.
+    ///
+    /// In structured mode, the end value is held back until the actor routine has
+    /// stopped all of its subordinates.
+    pub fn send_end(&self, end: Act::End) {
+        if self.end_sent.swap(true, Ordering::Relaxed) {
+            trace!("{}: actor already produced an end value, discarding another", self.path);
+            return;
+        }
+        if self.structured {
+            *lock(&self.pending_end) = Some(end);
+        } else {
+            self.send_end_now(end);
+        }
+    }
+
+    /// Send the end value which was held back in structured mode, if any.
+    pub fn flush_pending_end(&self) {
+        let pending = lock(&self.pending_end).take();
+        if let Some(end) = pending {
+            self.send_end_now(end);
+        }
+    }
+
+    fn send_end_now(&self, end: Act::End) {
+        let res = self.end_signal_send.unbounded_send((self.id, end));
+        if res.is_err() {
+            trace!("{}: actor was explicitly terminated, but parent is already dead", self.path);
+        }
+    }
+
+    /// Remove this actor from its registry and system, and notify its watchers,
+    /// once its routine terminates.
+    ///
+    /// Only the first call notifies watchers.
+    pub fn exit(&self, reason: ExitReason) {
+        self.registry.unregister_all(self.id);
+        if let Some(ref system) = self.system {
+            system.untrack(self.id);
+        }
+        self.mailbox.watchers.exit(reason);
+    }
+
+    /// Kill all subordinates, and take their termination signals.
+    pub fn kill_subordinates(&self) -> Vec<DropSignalRecv> {
+        let subordinates = mem::take(&mut *lock(&self.subordinates));
+        subordinates.into_values()
+            .map(|child| {
+                child.kill_signal.send();
+                child.terminated_recv
+            })
+            .collect()
+    }
+
+    /// Information about the live subordinates of this actor.
+    pub fn subordinate_infos(&self) -> Vec<ChildInfo> {
+        let mut subordinates = lock(&self.subordinates);
+        prune_subordinates(&mut subordinates);
+        subordinates.values()
+            .map(|child| child.info.clone())
+            .collect()
+    }
+
+    /// Forget a subordinate, once its end value has arrived.
+    pub fn untrack_subordinate(&self, id: ActorId) {
+        lock(&self.subordinates).remove(&id);
+    }
+
+    /// Create the link for a new subordinate of this actor.
+    ///
+    /// Unless a name is given, the subordinate is named by its id.
+    ///
+    /// Returns the link, and the subordinate's termination signal receiver, which
+    /// should be passed to `track_subordinate` once the subordinate is created.
+    fn link_subordinate<Sub>(
+        &self,
+        name: Option<String>,
+    ) -> (SupervisorLink<Sub>, DropSignalRecv)
+    where
+        Sub: Actor<End = Act::SubordinateEnd>,
+    {
+        let (terminated_send, terminated_recv) = drop_signal_channel();
+        let id = ActorId::next();
+        let path = match name {
+            Some(name) => self.path.child(&name),
+            None => self.path.child(&id.to_string()),
+        };
+        let link = SupervisorLink {
+            id,
+            path,
+            end_signal_send: self.subord_end_signal_send.clone(),
+            structured: self.structured,
+            terminated_send,
+            registry: self.registry.clone(),
+            system: self.system.clone(),
+        };
+        (link, terminated_recv)
+    }
+
+    /// Keep track of a newly created subordinate.
+    fn track_subordinate(
+        &self,
+        info: ChildInfo,
+        kill_signal: DropSignalArcWeak,
+        terminated_recv: DropSignalRecv,
+    ) {
+        let mut subordinates = lock(&self.subordinates);
+        prune_subordinates(&mut subordinates);
+        subordinates.insert(info.id(), ChildLink {
+            info,
+            kill_signal,
+            terminated_recv,
+        });
+    }
+}
+
+/// Forget subordinates which terminated without producing an end value.
+fn prune_subordinates(subordinates: &mut BTreeMap<ActorId, ChildLink>) {
+    subordinates.retain(|_, child| !child.terminated_recv.try_recv());
+}
+
+
+/// Make an actor watch another actor's termination, given the watching actor's
+/// shared state.
+pub fn watch<Act, T, O, F>(
+    watcher: &ActorStateShared<Act>,
+    mailbox: &Mailbox<T, O>,
+    f: F,
+)
+where
+    Act: Actor,
+    T: MessageTypeUnion,
+    O: Ownership,
+    F: FnOnce(ExitReason) -> <Act::Message as MessageTypeUnion>::MutUnion + Send + 'static,
+{
+    let system_send = watcher.system_send.clone();
+    mailbox.watchers.watch(Watcher::Watch(watcher.id), Box::new(move |reason| {
+        let entry = MsgQueueEntry::MailboxEntry(MailboxEntry::Mut(f(reason)), None);
+        if system_send.unbounded_send(entry).is_err() {
+            trace!("watched actor terminated, but watcher is already dead");
+        }
+    }));
+    watcher.mailbox.watchers.watching(mailbox.id(), Watcher::Watch(watcher.id), &mailbox.watchers);
+}
+
+/// Make an actor stop watching another actor's termination, given the watching
+/// actor's shared state.
+pub fn unwatch<Act, T, O>(watcher: &ActorStateShared<Act>, mailbox: &Mailbox<T, O>)
+where
+    Act: Actor,
+    T: MessageTypeUnion,
+    O: Ownership,
+{
+    mailbox.watchers.unwatch(Watcher::Watch(watcher.id));
+    watcher.mailbox.watchers.not_watching(mailbox.id(), Watcher::Watch(watcher.id));
+}
+
+/// Link an actor with another actor, given the first actor's shared state.
+pub fn link<Act, T, O>(actor: &ActorStateShared<Act>, mailbox: &Mailbox<T, O>)
+where
+    Act: Actor,
+    T: MessageTypeUnion,
+    O: Ownership,
+{
+    let (id, other_id) = (actor.id, mailbox.id());
+    let ours = &actor.mailbox.watchers;
+    let theirs = &mailbox.watchers;
+
+    let target = ours.target();
+    theirs.watch(Watcher::Link(id), Box::new(move |reason| {
+        target.linked_exit(other_id, reason)
+    }));
+    let target = theirs.target();
+    ours.watch(Watcher::Link(other_id), Box::new(move |reason| {
+        target.linked_exit(id, reason)
+    }));
+    ours.watching(other_id, Watcher::Link(id), theirs);
+    theirs.watching(id, Watcher::Link(other_id), ours);
+}
+
+/// Remove the link between an actor and another actor, given the first actor's
+/// shared state.
+pub fn unlink<Act, T, O>(actor: &ActorStateShared<Act>, mailbox: &Mailbox<T, O>)
+where
+    Act: Actor,
+    T: MessageTypeUnion,
+    O: Ownership,
+{
+    let (id, other_id) = (actor.id, mailbox.id());
+    let ours = &actor.mailbox.watchers;
+    let theirs = &mailbox.watchers;
+
+    theirs.unwatch(Watcher::Link(id));
+    ours.unwatch(Watcher::Link(other_id));
+    ours.not_watching(other_id, Watcher::Link(id));
+    theirs.not_watching(id, Watcher::Link(other_id));
+}
 
 /// Create a subordinate actor, given the manager actor's shared state.
 pub fn create_subordinate<Act1, Act2>(
     supervisor: &ActorStateShared<Act1>,
+    name: Option<String>,
     subordinate: Act2,
-) -> (SubordinateActor<Act2>, MailboxOwned<Act2::Message>)
+) -> (
+    SubordinateActor<Act2>,
+    MailboxOwned<Act2::Message>,
+    SubordinateHandle<Act2>,
+)
 where
     Act1: Actor,
     Act2: Actor<End = Act1::SubordinateEnd>,
 {
-    let (
-        actor,
-        msg_sender,
-        drop_signal_send,
-    ) = create_actor(
-        subordinate,
-        supervisor.subord_end_signal_send.clone(),
+    let (link, terminated_recv) = supervisor.link_subordinate(name);
+    let created = create_actor(subordinate, link);
+    let drop_signal = created.kill_signal_send;
+    let mailbox = MailboxOwned::new_owned(
+        created.mailbox,
+        drop_signal.clone(),
+    );
+    supervisor.track_subordinate(
+        ChildInfo::new(mailbox.clone().downgrade()),
+        drop_signal.downgrade(),
+        terminated_recv,
     );
 
     (
-        SubordinateActor::new(actor),
-        MailboxOwned::new_owned(
-            msg_sender,
-            drop_signal_send.arc(),
+        SubordinateActor::new(created.state),
+        mailbox,
+        SubordinateHandle::new(
+            drop_signal.downgrade(),
+            created.stop_send,
         ),
     )
 }
 
+/// Create a subordinate actor, and spawn it onto the spawner of the manager
+/// actor's system, given the manager actor's shared state.
+///
+/// Panics if the manager actor does not belong to an actor system.
+pub fn spawn_subordinate<Act1, Act2>(
+    supervisor: &ActorStateShared<Act1>,
+    name: Option<String>,
+    subordinate: Act2,
+) -> MailboxOwned<Act2::Message>
+where
+    Act1: Actor,
+    Act2: Actor<End = Act1::SubordinateEnd>,
+{
+    let system = supervisor.system.as_ref()
+        .expect("spawning a subordinate of an actor which is not in an actor system");
+    // as with an orphaned subordinate, dropping the handle does not terminate it
+    let (actor, mailbox, _) = create_subordinate(supervisor, name, subordinate);
+    system.spawn_task(Box::new(actor));
+    mailbox
+}
+
+/// Create a subordinate actor, given the manager actor's shared state, and spawn
+/// it onto a dedicated thread.
+pub fn spawn_subordinate_blocking<Act1, Act2>(
+    supervisor: &ActorStateShared<Act1>,
+    name: Option<String>,
+    subordinate: Act2,
+) -> MailboxOwned<Act2::Message>
+where
+    Act1: Actor,
+    Act2: Actor<End = Act1::SubordinateEnd>,
+{
+    let (actor, mailbox, _) = create_subordinate(supervisor, name, subordinate);
+    // if the thread cannot be created, the actor is dropped, as with a refusing spawner
+    if DedicatedThreads.spawn(Box::new(actor)).is_err() {
+        trace!("{}: failed to spawn thread for blocking actor, dropping it", mailbox.path());
+    }
+    mailbox
+}
+
 /// Create a subordinate actor socket, given the manager actor's
 /// shared state.
 pub fn create_subordinate_socket<Act1, Act2>(
     supervisor: &ActorStateShared<Act1>,
-) -> (ActorSocket<Act2>, MailboxOwned<Act2::Message>)
+) -> (
+    ActorSocket<Act2>,
+    MailboxOwned<Act2::Message>,
+    SubordinateHandle<Act2>,
+)
 where
     Act1: Actor,
     Act2: Actor<End = Act1::SubordinateEnd>,
 {
-    // create the message channels
-    // TODO: this is a copy/paste of code in `super::create`
-    //       and these fields are in severe need of packaging
-    //       into a struct
-    let (
-        mailbox_send,
-        mailbox_recv
-    ) = mpsc::channel(1000);
-    let (
-        sub_end_send,
-        sub_end_recv
-    ) = mpsc::unbounded();
-    let (
-        kil_sig_send,
-        kil_sig_recv,
-    ) = drop_signal_channel();
-
-    let msg_recv = MsgQueue::new(
-        kil_sig_recv,
-        mailbox_recv,
-        sub_end_recv,
+    let (link, terminated_recv) = supervisor.link_subordinate(None);
+    let channels = create_channels(&link);
+    let kil_sig_send = channels.kill_signal_send;
+    let mailbox = MailboxOwned::new_owned(
+        channels.inbox.mailbox.clone(),
+        kil_sig_send.clone(),
+    );
+    supervisor.track_subordinate(
+        ChildInfo::new(mailbox.clone().downgrade()),
+        kil_sig_send.downgrade(),
+        terminated_recv,
     );
 
     (
-        ActorSocket::new(
-            msg_recv,
-            supervisor.subord_end_signal_send.clone(),
-            sub_end_send,
-        ),
-        MailboxOwned::new_owned(
-            mailbox_send,
-            kil_sig_send.arc(),
+        ActorSocket::new(channels.inbox, link),
+        mailbox,
+        SubordinateHandle::new(
+            kil_sig_send.downgrade(),
+            channels.stop_send,
         ),
     )
-}
\ No newline at end of file
+}
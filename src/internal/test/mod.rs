@@ -1,126 +1,1572 @@
-/*
-extern crate tokio;
 extern crate pretty_env_logger;
 
+/// Model checking of the access protocol, which runs with
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_`.
+#[cfg(loom)]
+mod model;
+
+/// Randomized stress tests of the ordering guarantees of dispatch.
+#[cfg(feature = "thread-pool")]
+mod stress;
+
 use crate::*;
 use crate::msg_union::*;
-use crate::internal::*;
-use tokio::prelude::*;
+use crate::manage::{ActorSystem, AbnormalClose, DeadLetterReason, ExitReason};
+use crate::mailbox::MailboxWeak;
+use crate::spawn::Spawner;
+use crate::util::timer::Delay;
+use crate::testing::{TestExecutor, TestProbe};
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures::{Future, Async};
+use futures::executor::{self, Notify, Spawn};
+use smallvec::SmallVec;
 
 fn init_log() {
     use std::sync::Once;
 
     static INIT_LOG: Once = Once::new();
     INIT_LOG.call_once(|| {
-        pretty_env_logger::init();
+        let _ = pretty_env_logger::try_init();
+    })
+}
+
+fn shared<T: MessageTypeUnion>(msgs: Vec<T::SharedUnion>) -> MailboxEntry<T> {
+    MailboxEntry::Shared(msgs.into_iter().collect::<SmallVec<_>>())
+}
+
+/// Actor which records the messages it processes.
+struct Recorder {
+    log: Vec<u32>,
+}
+
+#[derive(Copy, Clone)]
+struct RecorderMsg;
+
+impl MessageTypeUnion for RecorderMsg {
+    type SharedUnion = RecorderShared;
+    type MutUnion = RecorderMut;
+}
+
+#[derive(Clone)]
+enum RecorderShared {
+    // park the guard, to keep the actor in shared access
+    Hold,
+    // push, if the guard can upgrade immediately
+    TryPush(u32),
+    // park a future which upgrades the guard
+    Upgrade,
+}
+
+enum RecorderMut {
+    Push(u32),
+    // push, then downgrade and park the guard
+    PushHold(u32),
+    // push through a guard mapped to the log, then downgrade and park the guard
+    PushMapped(u32),
+    End,
+}
+
+thread_local! {
+    // the guards parked by Hold messages on the test's thread
+    static HELD: RefCell<Vec<ActorGuardShared<Recorder>>> = const { RefCell::new(Vec::new()) };
+    // the upgrade parked by an Upgrade message on the test's thread
+    static UPGRADING: RefCell<Option<Spawn<ActorGuardUpgrade<Recorder>>>> = const { RefCell::new(None) };
+}
+
+impl Actor for Recorder {
+    type Message = RecorderMsg;
+    type End = Result<Vec<u32>, AbnormalClose>;
+    type SubordinateEnd = ();
+
+    fn handle_msg_shared(actor: ActorGuardShared<Self>, msg: RecorderShared) {
+        match msg {
+            RecorderShared::Hold => HELD.with(|held| held.borrow_mut().push(actor)),
+            RecorderShared::TryPush(n) => {
+                if let Ok(mut actor) = ActorGuardShared::try_upgrade(actor) {
+                    actor.log.push(n);
+                }
+            },
+            RecorderShared::Upgrade => {
+                let upgrade = executor::spawn(ActorGuardShared::upgrade(actor));
+                UPGRADING.with(|upgrading| *upgrading.borrow_mut() = Some(upgrade));
+            },
+        };
+    }
+
+    fn handle_msg_mut(mut actor: ActorGuardMut<Self>, msg: RecorderMut) {
+        match msg {
+            RecorderMut::Push(n) => actor.log.push(n),
+            RecorderMut::PushHold(n) => {
+                actor.log.push(n);
+                let actor = ActorGuardMut::downgrade(actor);
+                HELD.with(|held| held.borrow_mut().push(actor));
+            },
+            RecorderMut::PushMapped(n) => {
+                let mut log = ActorGuardMut::map(actor, |actor| &mut actor.log);
+                log.push(n);
+                let log = MappedActorGuardMut::downgrade(log);
+                HELD.with(|held| held.borrow_mut().push(MappedActorGuardShared::unmap(log)));
+            },
+            RecorderMut::End => {
+                let log = actor.log.clone();
+                ActorGuardMut::delete(actor, Ok(log));
+            },
+        };
+    }
+
+    fn handle_subordinate_end(_: ActorGuardMut<Self>, _: ()) {}
+}
+
+// notify handle for futures which a test polls by hand
+struct NoNotify;
+
+impl Notify for NoNotify {
+    fn notify(&self, _: usize) {}
+}
+
+// poll the upgrade parked by an Upgrade message, returning the guard once it upgrades
+fn poll_upgrade() -> Option<ActorGuardMut<Recorder>> {
+    UPGRADING.with(|upgrading| {
+        let mut upgrading = upgrading.borrow_mut();
+        let polled = upgrading.as_mut()
+            .expect("no upgrade is parked")
+            .poll_future_notify(&Arc::new(NoNotify), 0);
+        match polled {
+            Ok(Async::Ready(guard)) => {
+                *upgrading = None;
+                Some(guard)
+            },
+            Ok(Async::NotReady) => None,
+            Err(_) => panic!("another guard is already upgrading"),
+        }
     })
 }
 
+fn recorder_log(exec: &TestExecutor, mailbox: &mailbox::MailboxOwned<RecorderMsg>) -> Vec<u32> {
+    exec.block_on(mailbox.inspect::<Recorder, _, _>(|actor| actor.log.clone()))
+        .unwrap_or_else(|_| panic!("actor died before inspection"))
+}
+
+#[test]
+fn test_dispatch() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    for n in 0..3 {
+        assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(n))).is_ok());
+    }
+    exec.run_until_idle();
+    assert!(exec.is_idle());
+    assert_eq!(recorder_log(&exec, &mailbox), vec![0, 1, 2]);
+
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::End)).is_ok());
+    assert_eq!(exec.block_on(end).unwrap(), vec![0, 1, 2]);
+}
+
 #[test]
-fn test_a() {
-    // very simple use test of internal dispatching
+fn test_step() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold, RecorderShared::Hold])).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(1))).is_ok());
+
+    // each step processes one message of the batch
+    assert!(exec.step());
+    assert_eq!(HELD.with(|held| held.borrow().len()), 1);
+    assert!(exec.step());
+    assert_eq!(HELD.with(|held| held.borrow().len()), 2);
 
+    // the mut message waits for the held guards
+    exec.run_until_idle();
+    assert!(!exec.step());
+    HELD.with(|held| held.borrow_mut().clear());
+    assert!(exec.step());
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+}
+
+#[test]
+fn test_shared_excludes_mut() {
     init_log();
 
-    use smallvec::smallvec;
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
 
-    tokio::run(future::lazy(|| {
-        struct Foo;
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold, RecorderShared::Hold])).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(1))).is_ok());
+    exec.run_until_idle();
 
-        impl Actor for Foo {
-            type Message = ();
+    // the mut message is blocked while shared guards are held
+    assert_eq!(HELD.with(|held| held.borrow().len()), 2);
+    assert!(exec.is_idle());
+
+    // releasing one guard is not enough
+    HELD.with(|held| held.borrow_mut().pop());
+    assert_eq!(exec.run_until_idle(), 0);
+
+    // releasing the last guard notifies the actor
+    HELD.with(|held| held.borrow_mut().clear());
+    assert!(!exec.is_idle());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+}
 
-            type End = ();
+#[test]
+#[should_panic(expected = "executor is idle")]
+fn test_block_on_idle() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mailbox, end) = system.spawn_root(Recorder { log: Vec::new() });
+    drop(mailbox);
+
+    // a root actor owns its own mailbox, so it never ends unless it deletes itself
+    let _ = exec.block_on(end);
+}
+
+#[test]
+fn test_try_upgrade() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    // the only shared guard upgrades
+    assert!(mailbox.send_now(shared(vec![RecorderShared::TryPush(1)])).is_ok());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+
+    // a guard which is not the only one does not
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold, RecorderShared::TryPush(2)])).is_ok());
+    exec.run_until_idle();
+    HELD.with(|held| held.borrow_mut().clear());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+}
+
+#[test]
+fn test_upgrade() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold, RecorderShared::Upgrade])).is_ok());
+    exec.run_until_idle();
+    assert!(poll_upgrade().is_none());
+
+    // the pending upgrade blocks new shared accesses
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    exec.run_until_idle();
+    assert_eq!(HELD.with(|held| held.borrow().len()), 1);
+
+    // once the other guard releases, the upgrade completes
+    HELD.with(|held| held.borrow_mut().clear());
+    let mut actor = poll_upgrade().expect("upgrade did not complete");
+    actor.log.push(2);
+    drop(actor);
+
+    exec.run_until_idle();
+    assert_eq!(HELD.with(|held| held.borrow().len()), 1);
+    HELD.with(|held| held.borrow_mut().clear());
+    assert_eq!(recorder_log(&exec, &mailbox), vec![2]);
+}
+
+#[test]
+fn test_upgrade_abandoned() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold, RecorderShared::Upgrade])).is_ok());
+    exec.run_until_idle();
+    assert!(poll_upgrade().is_none());
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    exec.run_until_idle();
+    assert_eq!(HELD.with(|held| held.borrow().len()), 1);
+
+    // dropping the pending upgrade lets shared access resume, while the other guard
+    // is still held
+    UPGRADING.with(|upgrading| upgrading.borrow_mut().take());
+    exec.run_until_idle();
+    assert_eq!(HELD.with(|held| held.borrow().len()), 2);
+
+    HELD.with(|held| held.borrow_mut().clear());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(1))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+}
+
+#[test]
+fn test_downgrade() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    // shared access begins while the downgraded guard is held, but mut access does not
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::PushHold(1))).is_ok());
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(2))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(HELD.with(|held| held.borrow().len()), 2);
+
+    HELD.with(|held| held.borrow_mut().clear());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1, 2]);
+}
+
+#[test]
+fn test_mapped_guards() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::PushMapped(1))).is_ok());
+    exec.run_until_idle();
+    let actor = HELD.with(|held| held.borrow_mut().pop()).expect("no guard is held");
+
+    // mapped guards project into the state, and keep the actor accessed
+    let log = ActorGuardShared::map(actor, |actor| &actor.log);
+    let first = MappedActorGuardShared::map(log.clone(), |log| &log[0]);
+    assert_eq!(*log, vec![1]);
+    assert_eq!(*first, 1);
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(2))).is_ok());
+    exec.run_until_idle();
+    drop(log);
+    assert_eq!(exec.run_until_idle(), 0);
+    drop(first);
+
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1, 2]);
+}
+
+#[test]
+fn test_inspect() {
+    init_log();
 
-            type SubordinateEnd = ();
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    // an inspection is queued behind the messages sent before it
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(1))).is_ok());
+    let inspect = mailbox.inspect::<Recorder, _, _>(|actor| actor.log.len());
+    assert_eq!(exec.block_on(inspect).ok(), Some(1));
+
+    // it runs under shared access, alongside other shared guards
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    let inspect = mailbox.inspect::<Recorder, _, _>(|actor| actor.log.clone());
+    assert_eq!(exec.block_on(inspect).ok(), Some(vec![1]));
+    HELD.with(|held| held.borrow_mut().clear());
+
+    // it fails once the actor is dead
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::End)).is_ok());
+    exec.run_until_idle();
+    let inspect = mailbox.inspect::<Recorder, _, _>(|actor| actor.log.len());
+    assert!(exec.block_on(inspect).is_err());
+}
+
+/// Spawner which counts the tasks spawned onto it, and spawns them onto a test executor.
+struct CountingSpawner {
+    exec: TestExecutor,
+    spawned: Arc<AtomicUsize>,
+}
+
+impl Spawner for CountingSpawner {
+    fn spawn(&self, task: spawn::BoxedTask) -> Result<(), spawn::BoxedTask> {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        self.exec.spawn(task)
+    }
+}
+
+// spawn a root actor onto a test executor, discarding its end value
+fn spawn_root<Act>(exec: &TestExecutor, root: RootActor<Act>)
+where
+    Act: Actor,
+    Act::End: manage::IntoResult,
+    <Act::End as manage::IntoResult>::Error: From<AbnormalClose>,
+{
+    exec.spawn(Box::new(root.then(|_| Ok(())))).ok().unwrap();
+}
+
+#[test]
+fn test_parallel_shared() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let root = RootActor::new(Recorder { log: Vec::new() })
+        .parallel_shared(CountingSpawner { exec: exec.clone(), spawned: spawned.clone() });
+    let mut mailbox = root.mailbox().clone();
+    spawn_root(&exec, root);
+
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold; 3])).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(1))).is_ok());
+    exec.run_until_idle();
+
+    // each message of the batch was handled as its own task
+    assert_eq!(spawned.load(Ordering::SeqCst), 3);
+    assert_eq!(HELD.with(|held| held.borrow().len()), 3);
+
+    // the actor stays in shared access until every handler's guard is released
+    HELD.with(|held| held.borrow_mut().truncate(1));
+    assert_eq!(exec.run_until_idle(), 0);
+    HELD.with(|held| held.borrow_mut().clear());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+}
+
+/// Actor which forwards its messages to another actor, until it receives a 0.
+struct Echo {
+    to: MailboxWeak<EchoMsg>,
+}
+
+#[derive(Copy, Clone)]
+struct EchoMsg;
+
+impl MessageTypeUnion for EchoMsg {
+    type SharedUnion = u32;
+    type MutUnion = u32;
+}
+
+impl Actor for Echo {
+    type Message = EchoMsg;
+    type End = Result<u32, AbnormalClose>;
+    type SubordinateEnd = ();
+
+    fn handle_msg_shared(actor: ActorGuardShared<Self>, n: u32) {
+        let _ = actor.to.clone().send_now(shared(vec![n, n]));
+    }
+
+    fn handle_msg_mut(mut actor: ActorGuardMut<Self>, n: u32) {
+        if n == 0 {
+            ActorGuardMut::delete(actor, Ok(n));
+        } else {
+            let _ = actor.to.send_now(MailboxEntry::Mut(n));
         }
+    }
 
-        let actor = Foo;
-        let (
-            state,
-            mut send,
-        ) = create::create_actor(actor);
+    fn handle_subordinate_end(_: ActorGuardMut<Self>, _: ()) {}
+}
 
-        tokio::spawn(state);
+#[test]
+fn test_probe_records() {
+    init_log();
 
-        send.try_send(ActorMessage::Shared(smallvec![(), (), ()])).unwrap();
-        send.try_send(ActorMessage::Mut(())).unwrap();
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let mut probe = TestProbe::<EchoMsg>::new()
+        .with_timeout(Duration::from_secs(1))
+        .with_executor(&exec);
+    let (mut mailbox, _end) = system.spawn_root(Echo { to: probe.mailbox_handle() });
 
-        Ok(())
-    }));
+    assert!(mailbox.send_now(MailboxEntry::Mut(1)).is_ok());
+    assert!(mailbox.send_now(shared(vec![2])).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(3)).is_ok());
+    exec.run_until_idle();
+
+    assert_eq!(probe.expect_mut(), 1);
+    assert_eq!(probe.expect_shared().into_vec(), vec![2, 2]);
+    assert_eq!(probe.expect_mut(), 3);
+    probe.expect_no_message(Duration::from_millis(10));
+}
+
+#[test]
+fn test_probe_clock() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let mut probe = TestProbe::<EchoMsg>::new().with_executor(&exec);
+    let start = exec.clock().now();
+
+    // a message which is sent once a timer fires
+    let mut mailbox = probe.mailbox().clone();
+    let task = Delay::new(start + Duration::from_secs(2))
+        .map(move |()| assert!(mailbox.send_now(MailboxEntry::Mut(1)).is_ok()));
+    exec.spawn(Box::new(task)).ok().unwrap();
+
+    // the probe's waits advance the mock clock, only as far as needed
+    probe.expect_no_message(Duration::from_secs(1));
+    assert_eq!(exec.clock().now(), start + Duration::from_secs(1));
+    assert_eq!(probe.expect_mut(), 1);
+    assert_eq!(exec.clock().now(), start + Duration::from_secs(2));
+    probe.expect_no_message(Duration::from_secs(3600));
+    assert_eq!(exec.clock().now(), start + Duration::from_secs(3602));
+}
+
+#[test]
+#[should_panic(expected = "expected shared message, received mut message")]
+fn test_probe_unexpected() {
+    let mut probe = TestProbe::<EchoMsg>::new();
+    assert!(probe.mailbox().clone().send_now(MailboxEntry::Mut(1)).is_ok());
+    probe.expect_shared();
+}
+
+#[test]
+fn test_probe_supervises() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let mut probe = TestProbe::<EchoMsg, Result<u32, AbnormalClose>>::new().with_executor(&exec);
+    let (actor, mut mailbox, _handle) = probe.manage(Echo { to: probe.mailbox_handle() });
+    exec.spawn(Box::new(actor)).ok().unwrap();
+
+    assert!(mailbox.send_now(MailboxEntry::Mut(5)).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(0)).is_ok());
+    exec.run_until_idle();
+
+    // like an actor, the probe receives subordinate ends before mailbox entries
+    let (id, end) = probe.expect_subordinate_end();
+    assert_eq!(id, mailbox.id());
+    assert_eq!(end, Ok(0));
+    assert_eq!(probe.expect_mut(), 5);
 }
 
 #[test]
-fn test_b() {
-    // log test which involves crossing thread boundaries, and verifying
-    // synchronization using an RwLock
+fn test_subordinate_stop() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let mut probe = TestProbe::<EchoMsg, Result<u32, AbnormalClose>>::new().with_executor(&exec);
+    let (actor, mut mailbox, handle) = probe.manage(Echo { to: probe.mailbox_handle() });
+    exec.spawn(Box::new(actor)).ok().unwrap();
+
+    // a stopped subordinate ends with the given value, even with its mailbox alive
+    handle.stop(Ok(7));
+    exec.run_until_idle();
+    assert_eq!(probe.expect_subordinate_end(), (mailbox.id(), Ok(7)));
+
+    // and processes no more messages
+    assert!(mailbox.send_now(MailboxEntry::Mut(5)).is_ok());
+    exec.run_until_idle();
+    probe.expect_no_message(Duration::from_millis(10));
+}
 
+#[test]
+fn test_subordinate_kill() {
     init_log();
 
-    use std::sync::RwLock;
-    use std::mem::drop;
-    use smallvec::smallvec;
-    use std::thread::{sleep, spawn};
-    use std::time::Duration;
+    let exec = TestExecutor::new();
+    let mut probe = TestProbe::<EchoMsg, Result<u32, AbnormalClose>>::new().with_executor(&exec);
+    let (actor, mut mailbox, handle) = probe.manage(Echo { to: probe.mailbox_handle() });
+    exec.spawn(Box::new(actor)).ok().unwrap();
+    assert!(mailbox.send_now(MailboxEntry::Mut(5)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(probe.expect_mut(), 5);
+
+    // a killed subordinate ends without an end value
+    handle.kill();
+    exec.run_until_idle();
+    assert!(mailbox.send_now(MailboxEntry::Mut(6)).is_ok());
+    exec.run_until_idle();
+    probe.expect_no_message(Duration::from_millis(10));
+}
+
+/// Actor which builds a chain of subordinates, and records what happens to its tree.
+struct Node {
+    name: &'static str,
+    events: Arc<Mutex<Vec<String>>>,
+    // the executor onto which subordinates are spawned, rather than the system's
+    exec: Option<TestExecutor>,
+    children: Vec<mailbox::MailboxOwned<NodeMsg>>,
+}
 
-    tokio::run(future::lazy(|| {
-        struct Foo(RwLock<()>);
+#[derive(Copy, Clone)]
+struct NodeMsg;
 
-        impl Actor for Foo {
-            type Message = FooUnion;
+impl MessageTypeUnion for NodeMsg {
+    type SharedUnion = ();
+    type MutUnion = NodeMut;
+}
 
-            type End = ();
+enum NodeMut {
+    // create a chain of subordinates with the given names
+    Spawn(Vec<&'static str>),
+    // create a subordinate on a dedicated thread, and have it record its thread
+    SpawnBlocking(&'static str),
+    // record the name of the thread this is processed on
+    Thread,
+    // send Thread with send_blocking, and record whether it was sent
+    SendBlocking(MailboxWeak<NodeMsg>),
+    // record the names of the live subordinates
+    Children,
+    // end the subordinate with the given name, through its child info
+    EndChild(&'static str),
+    // record this actor's path, and pass the message on to the subordinates
+    Identify,
+    // register under a name, and record whether it was taken
+    Register(&'static str),
+    // unregister a name, and record whether it was removed
+    Unregister(&'static str),
+    Watch(MailboxWeak<NodeMsg>),
+    Unwatch(MailboxWeak<NodeMsg>),
+    Link(MailboxWeak<NodeMsg>),
+    // trap the exits of linked actors as Exited messages
+    TrapExits,
+    // record the exit of a watched or linked actor
+    Exited(ExitReason),
+    // panic in the handler
+    Panic,
+    End,
+}
 
-            type SubordinateEnd = ();
+impl Node {
+    fn new(name: &'static str, events: &Arc<Mutex<Vec<String>>>, exec: Option<TestExecutor>) -> Self {
+        Node {
+            name,
+            events: events.clone(),
+            exec,
+            children: Vec::new(),
         }
+    }
 
-        struct FooShared;
-        struct FooMut;
-        #[derive(Copy, Clone)]
-        struct FooUnion;
-
-        impl MessageUnionShared<Foo> for FooShared {
-            fn process(self, actor: ActorGuardShared<Foo>) {
-                spawn(move || {
-                    debug!("+ acq shared");
-                    let lock = actor.0.try_read().unwrap();
-                    sleep(Duration::from_secs(1));
-                    drop(lock);
-                    debug!("- rel shared");
-                });
-            }
+    fn record(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.record(format!("drop {}", self.name));
+    }
+}
+
+impl Actor for Node {
+    type Message = NodeMsg;
+    type End = Result<&'static str, AbnormalClose>;
+    type SubordinateEnd = Result<&'static str, AbnormalClose>;
+
+    fn handle_msg_shared(_: ActorGuardShared<Self>, (): ()) {}
+
+    fn handle_msg_mut(mut actor: ActorGuardMut<Self>, msg: NodeMut) {
+        match msg {
+            NodeMut::Spawn(mut names) => {
+                let name = names.remove(0);
+                let child = Node::new(name, &actor.events, actor.exec.clone());
+                let mut mailbox = match actor.exec {
+                    Some(ref exec) => {
+                        let (sub, mailbox, _handle) = actor.manage_named(name, child);
+                        exec.spawn(Box::new(sub)).ok().unwrap();
+                        mailbox
+                    },
+                    None => actor.spawn_named(name, child),
+                };
+                if !names.is_empty() {
+                    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(names))).is_ok());
+                }
+                actor.children.push(mailbox);
+            },
+            NodeMut::SpawnBlocking(name) => {
+                let child = Node::new(name, &actor.events, None);
+                let mut mailbox = actor.spawn_blocking_named(name, child);
+                assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Thread)).is_ok());
+                actor.children.push(mailbox);
+            },
+            NodeMut::Thread => {
+                let thread = std::thread::current();
+                actor.record(format!("{} on {}", actor.name, thread.name().unwrap_or("?")));
+            },
+            NodeMut::SendBlocking(mut mailbox) => {
+                let sent = mailbox.send_blocking(MailboxEntry::Mut(NodeMut::Thread)).is_ok();
+                actor.record(format!("{} sent {}", actor.name, sent));
+            },
+            NodeMut::Children => {
+                let names: Vec<String> = actor.children().iter()
+                    .map(|child| child.name().to_owned())
+                    .collect();
+                actor.record(format!("children {}", names.join(",")));
+            },
+            NodeMut::EndChild(name) => {
+                let child = actor.children().into_iter()
+                    .find(|child| child.name() == name)
+                    .expect("no such child");
+                assert!(child.mailbox::<RecorderMsg>().is_none());
+                let mut mailbox = child.mailbox::<NodeMsg>().unwrap();
+                assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+            },
+            NodeMut::Identify => {
+                actor.record(actor.path().to_string());
+                for child in &mut actor.children {
+                    assert!(child.send_now(MailboxEntry::Mut(NodeMut::Identify)).is_ok());
+                }
+            },
+            NodeMut::Register(name) => {
+                let registered = actor.register(name).is_ok();
+                actor.record(format!("register {} {}", name, registered));
+            },
+            NodeMut::Unregister(name) => {
+                let unregistered = actor.unregister(name);
+                actor.record(format!("unregister {} {}", name, unregistered));
+            },
+            NodeMut::Watch(mailbox) => actor.watch(&mailbox, NodeMut::Exited),
+            NodeMut::Unwatch(mailbox) => actor.unwatch(&mailbox),
+            NodeMut::Link(mailbox) => actor.link(&mailbox),
+            NodeMut::TrapExits => actor.trap_exits(|_, reason| NodeMut::Exited(reason)),
+            NodeMut::Exited(reason) => actor.record(format!("exited {:?}", reason)),
+            NodeMut::Panic => panic!("{} panicked", actor.name),
+            NodeMut::End => {
+                let name = actor.name;
+                ActorGuardMut::delete(actor, Ok(name));
+            },
+        };
+    }
+
+    fn handle_subordinate_end(actor: ActorGuardMut<Self>, end: Result<&'static str, AbnormalClose>) {
+        if let Ok(name) = end {
+            actor.record(format!("end {}", name));
         }
+    }
+}
 
-        impl MessageUnionMut<Foo> for FooMut {
-            fn process(self, actor: ActorGuardMut<Foo>) {
-                spawn(move || {
-                    debug!("+ acq mut");
-                    let lock = actor.0.try_write().unwrap();
-                    sleep(Duration::from_secs(1));
-                    drop(lock);
-                    debug!("- rel mut");
+fn events(events: &Arc<Mutex<Vec<String>>>) -> Vec<String> {
+    events.lock().unwrap().clone()
+}
+
+#[test]
+fn test_structured() {
+    init_log();
+
+    // subordinates run on their own executor, so that the root can be observed
+    // while they are still alive
+    let exec = TestExecutor::new();
+    let subs = TestExecutor::new();
+    for &structured in &[true, false] {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let node = Node::new("root", &log, Some(subs.clone()));
+        let root = if structured {
+            RootActor::new_structured(node)
+        } else {
+            RootActor::new(node)
+        };
+        let mut mailbox = root.mailbox().clone();
+        let ended = Arc::new(Mutex::new(None));
+        let ended_2 = ended.clone();
+        let task = root.then(move |end| {
+            *ended_2.lock().unwrap() = Some(end.ok());
+            Ok(())
+        });
+        exec.spawn(Box::new(task)).ok().unwrap();
+
+        assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["a", "b"]))).is_ok());
+        exec.run_until_idle();
+        subs.run_until_idle();
+        assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+        exec.run_until_idle();
+
+        if structured {
+            // the end value is held back until the subordinates have terminated
+            assert_eq!(*ended.lock().unwrap(), None);
+            assert_eq!(events(&log), vec!["drop root"]);
+            subs.run_until_idle();
+            exec.run_until_idle();
+        }
+        assert_eq!(*ended.lock().unwrap(), Some(Some("root")));
+        subs.run_until_idle();
+        let mut dropped = events(&log);
+        dropped.sort();
+        assert_eq!(dropped, vec!["drop a", "drop b", "drop root"]);
+    }
+}
+
+#[test]
+fn test_children() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+
+    for msg in [
+        NodeMut::Spawn(vec!["a"]),
+        NodeMut::Spawn(vec!["b"]),
+        NodeMut::Children,
+        NodeMut::EndChild("a"),
+    ] {
+        assert!(mailbox.send_now(MailboxEntry::Mut(msg)).is_ok());
+    }
+    exec.run_until_idle();
+
+    // a subordinate is forgotten once its end value arrives
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Children)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log), vec!["children a,b", "drop a", "end a", "children b"]);
+}
+
+#[test]
+fn test_identity() {
+    use std::collections::HashSet;
+
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+    let (other, _other_end) = system.spawn_root(Node::new("other", &log, None));
+
+    // paths are formed from the names of the supervisor chain
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["a", "b"]))).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["c"]))).is_ok());
+    exec.run_until_idle();
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Identify)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log), vec!["/root", "/root/a", "/root/c", "/root/a/b"]);
+    assert_eq!(mailbox.path().name(), "root");
+
+    // mailboxes are equal by actor, regardless of ownership
+    let weak = mailbox.clone().downgrade();
+    assert_eq!(mailbox, weak);
+    assert_eq!(weak.id(), mailbox.id());
+    assert!(mailbox != other);
+    // mailboxes hash only by the actor id, which is immutable
+    #[allow(clippy::mutable_key_type)]
+    let set: HashSet<_> = vec![mailbox.clone(), mailbox.clone(), other.clone()].into_iter().collect();
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn test_registry() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let registry = system.registry();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+    let (mut other, _other_end) = system.spawn_root(Node::new("other", &log, None));
+
+    // names are unique across the system
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Register("service"))).is_ok());
+    exec.run_until_idle();
+    assert!(other.send_now(MailboxEntry::Mut(NodeMut::Register("service"))).is_ok());
+    assert!(other.send_now(MailboxEntry::Mut(NodeMut::Unregister("service"))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log), vec![
+        "register service true",
+        "register service false",
+        "unregister service false",
+    ]);
+
+    // lookups are typed
+    assert_eq!(registry.lookup::<Node>("service"), Some(mailbox.clone().downgrade()));
+    assert!(registry.lookup::<Recorder>("service").is_none());
+    assert!(registry.lookup::<Node>("missing").is_none());
+
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Unregister("service"))).is_ok());
+    exec.run_until_idle();
+    assert!(!registry.contains("service"));
+
+    // names are removed once their actor terminates
+    assert!(other.send_now(MailboxEntry::Mut(NodeMut::Register("service"))).is_ok());
+    exec.run_until_idle();
+    assert!(registry.contains("service"));
+    assert!(other.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    exec.run_until_idle();
+    assert!(!registry.contains("service"));
+}
+
+#[test]
+fn test_watch() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut watcher, _end) = system.spawn_root(Node::new("watcher", &log, None));
+    let (mut watched, _watched_end) = system.spawn_root(Node::new("watched", &log, None));
+    let (mut unwatched, _unwatched_end) = system.spawn_root(Node::new("unwatched", &log, None));
+
+    for msg in [
+        NodeMut::Watch(watched.clone().downgrade()),
+        NodeMut::Watch(unwatched.clone().downgrade()),
+        NodeMut::Unwatch(unwatched.clone().downgrade()),
+    ] {
+        assert!(watcher.send_now(MailboxEntry::Mut(msg)).is_ok());
+    }
+    exec.run_until_idle();
+    assert!(watched.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    assert!(unwatched.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log), vec!["drop watched", "drop unwatched", "exited Ended"]);
+
+    // watching an actor which already terminated notifies immediately
+    assert!(watcher.send_now(MailboxEntry::Mut(NodeMut::Watch(watched.clone().downgrade()))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log).last().map(String::as_str), Some("exited Ended"));
+    assert_eq!(events(&log).len(), 4);
+
+    // a watcher's callbacks are removed once it terminates
+    let (target, _target_end) = system.spawn_root(Node::new("target", &log, None));
+    assert!(watcher.send_now(MailboxEntry::Mut(NodeMut::Watch(target.clone().downgrade()))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(target.watchers.len(), 1);
+    assert!(watcher.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(target.watchers.len(), 0);
+}
+
+#[test]
+fn test_link() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut a, _a_end) = system.spawn_root(Node::new("a", &log, None));
+    let probe = TestProbe::<NodeMsg, Result<&'static str, AbnormalClose>>::new();
+    let (b_actor, b, b_handle) = probe.manage(Node::new("b", &log, None));
+    exec.spawn(Box::new(b_actor)).ok().unwrap();
+    let (mut c, _c_end) = system.spawn_root(Node::new("c", &log, None));
+    let (mut d, _d_end) = system.spawn_root(Node::new("d", &log, None));
+
+    assert!(a.send_now(MailboxEntry::Mut(NodeMut::Link(b.clone().downgrade()))).is_ok());
+    assert!(c.send_now(MailboxEntry::Mut(NodeMut::TrapExits)).is_ok());
+    assert!(c.send_now(MailboxEntry::Mut(NodeMut::Link(a.clone().downgrade()))).is_ok());
+    assert!(c.send_now(MailboxEntry::Mut(NodeMut::Link(d.clone().downgrade()))).is_ok());
+    exec.run_until_idle();
+
+    // an abnormal exit kills linked actors, and is trapped by trapping actors
+    b_handle.kill();
+    exec.run_until_idle();
+    assert_eq!(events(&log), vec!["drop b", "drop a", "exited Killed"]);
+
+    // a normal exit does not kill linked actors
+    assert!(d.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log)[3..], ["drop d", "exited Ended"]);
+    assert!(c.send_now(MailboxEntry::Mut(NodeMut::Identify)).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log)[5..], ["/root"]);
+}
+
+#[test]
+fn test_system_shutdown() {
+    use crate::spawn::blocking::DedicatedThreads;
+    use std::time::Instant;
+
+    init_log();
+
+    // the system's actors must run on other threads, while shutdown blocks
+    let system = ActorSystem::new(DedicatedThreads);
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, end) = system.spawn_root(Node::new("root", &log, None));
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["a", "b"]))).is_ok());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while system.live_actors().len() < 3 {
+        assert!(Instant::now() < deadline, "actors were not spawned");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let mut paths: Vec<String> = system.live_actors().into_iter()
+        .map(|(_, path)| path.to_string())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, vec!["/root", "/root/a", "/root/a/b"]);
+
+    assert_eq!(system.shutdown(Duration::from_secs(5)), Ok(()));
+    assert!(system.live_actors().is_empty());
+    assert!(end.wait().is_err());
+    let mut dropped = events(&log);
+    dropped.sort();
+    assert_eq!(dropped, vec!["drop a", "drop b", "drop root"]);
+
+    // actors spawned after shutdown are killed immediately
+    let (_mailbox, end) = system.spawn_root(Node::new("late", &log, None));
+    assert!(end.wait().is_err());
+}
+
+#[test]
+fn test_system_shutdown_timeout() {
+    init_log();
+
+    // nothing runs the executor, so the actor can't terminate
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (_mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+    let result = system.shutdown(Duration::from_millis(10));
+    assert_eq!(result, Err(manage::ShutdownTimeout { remaining: 1 }));
+
+    // once it runs, it has already been killed
+    exec.run_until_idle();
+    assert!(system.live_actors().is_empty());
+}
+
+// run a small actor tree on a spawner, which must run its tasks on other threads
+fn run_tree<S: Spawner>(spawner: S) {
+    let system = ActorSystem::new(spawner);
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, end) = system.spawn_root(Node::new("root", &log, None));
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["a"]))).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Identify)).is_ok());
+    // the subordinate stops processing messages once the root ends, so wait for it
+    await_events(&log, 2);
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    assert_eq!(end.wait(), Ok("root"));
+    assert_eq!(system.shutdown(Duration::from_secs(5)), Ok(()));
+    assert_eq!(events(&log)[..2], ["/root", "/root/a"]);
+}
+
+#[test]
+fn test_executor_spawner() {
+    init_log();
+
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+    run_tree(spawn::ExecutorSpawner(runtime.executor()));
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn test_tokio_spawner() {
+    init_log();
+
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+    run_tree(runtime.executor());
+}
+
+#[test]
+#[cfg(feature = "async-std")]
+fn test_async_std_spawner() {
+    init_log();
+
+    run_tree(spawn::async_std::AsyncStdSpawner);
+}
+
+#[test]
+#[cfg(feature = "thread-pool")]
+fn test_thread_pool_spawner() {
+    init_log();
+
+    run_tree(spawn::thread_pool::ThreadPool::new(2));
+}
+
+#[test]
+#[cfg(feature = "thread-pool")]
+fn test_thread_pool() {
+    use futures::sync::oneshot;
+    use std::sync::mpsc;
+
+    init_log();
+
+    let pool = spawn::thread_pool::ThreadPool::new(4);
+    let (done_send, done_recv) = mpsc::channel();
+
+    // tasks spawned from the pool's threads run as well as those spawned from outside
+    for i in 0..10 {
+        let inner_pool = pool.clone();
+        let done_send = done_send.clone();
+        let task = futures::future::lazy(move || {
+            for j in 0..10 {
+                let done_send = done_send.clone();
+                let task = futures::future::lazy(move || {
+                    done_send.send(i * 10 + j).unwrap();
+                    Ok(())
                 });
+                assert!(inner_pool.spawn(Box::new(task)).is_ok());
             }
+            Ok(())
+        });
+        assert!(pool.spawn(Box::new(task)).is_ok());
+    }
+    let mut done: Vec<u32> = (0..100)
+        .map(|_| done_recv.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    done.sort();
+    assert_eq!(done, (0..100).collect::<Vec<_>>());
+
+    // tasks are run again once woken from outside of the pool
+    let (wake_send, wake_recv) = oneshot::channel::<u32>();
+    let task = wake_recv.then(move |n| {
+        done_send.send(n.unwrap()).unwrap();
+        Ok(())
+    });
+    assert!(pool.spawn(Box::new(task)).is_ok());
+    std::thread::sleep(Duration::from_millis(10));
+    wake_send.send(7).unwrap();
+    assert_eq!(done_recv.recv_timeout(Duration::from_secs(5)), Ok(7));
+}
+
+#[test]
+#[cfg(feature = "thread-pool")]
+fn test_thread_pool_panic() {
+    init_log();
+
+    // an actor which panics is dropped, and the pool's only thread goes on to run others
+    let system = ActorSystem::new(spawn::thread_pool::ThreadPool::new(1));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut a, a_end) = system.spawn_root(Node::new("a", &log, None));
+    let (mut b, b_end) = system.spawn_root(Node::new("b", &log, None));
+    assert!(a.send_now(MailboxEntry::Mut(NodeMut::Panic)).is_ok());
+    assert!(a_end.wait().is_err());
+    assert!(b.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    assert_eq!(b_end.wait(), Ok("b"));
+}
+
+#[test]
+#[cfg(feature = "thread-pool")]
+fn test_run() {
+    init_log();
+
+    // the root spawns a subordinate onto the pool
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let end = crate::run(Node::new("root", &log, None), |mut mailbox| {
+        assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Spawn(vec!["a"]))).is_ok());
+        assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Children)).is_ok());
+        assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    });
+    assert_eq!(end, Ok("root"));
+    assert_eq!(events(&log)[0], "children a");
+}
+
+/// Local actor which records the messages it processes, in state which is not `Send`.
+struct LocalRecorder {
+    log: std::rc::Rc<RefCell<Vec<String>>>,
+}
+
+#[derive(Copy, Clone)]
+struct LocalRecorderMsg;
+
+impl local::LocalMessageTypeUnion for LocalRecorderMsg {
+    // if the message is 0, the guard is held by another task until it runs
+    type SharedUnion = u32;
+    // if the message is 0, the actor ends
+    type MutUnion = u32;
+}
+
+impl LocalRecorder {
+    fn record(&self, event: String) {
+        self.log.borrow_mut().push(event);
+    }
+}
+
+impl Drop for LocalRecorder {
+    fn drop(&mut self) {
+        self.record("drop".to_owned());
+    }
+}
+
+impl local::LocalActor for LocalRecorder {
+    type Message = LocalRecorderMsg;
+    type End = Result<usize, AbnormalClose>;
+
+    fn handle_msg_shared(actor: local::LocalGuardShared<Self>, n: u32) {
+        actor.record(format!("shared {}", n));
+        if n == 0 {
+            local::spawn(futures::future::lazy(move || {
+                actor.record("release".to_owned());
+                Ok(())
+            }));
+        }
+    }
+
+    fn handle_msg_mut(actor: local::LocalGuardMut<Self>, n: u32) {
+        actor.record(format!("mut {}", n));
+        if n == 0 {
+            let len = actor.log.borrow().len();
+            local::LocalGuardMut::delete(actor, Ok(len));
+        }
+    }
+}
+
+fn local_send(root: &local::LocalRootActor<LocalRecorder>, entry: local::LocalMailboxEntry<LocalRecorderMsg>) {
+    root.mailbox().send(entry);
+}
+
+#[test]
+fn test_local_dispatch() {
+    use crate::local::LocalMailboxEntry;
+
+    init_log();
+
+    let log = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let root = local::LocalRootActor::new(LocalRecorder { log: log.clone() });
+    local_send(&root, LocalMailboxEntry::Mut(1));
+    local_send(&root, LocalMailboxEntry::Shared(vec![2, 0, 3].into_iter().collect()));
+    local_send(&root, LocalMailboxEntry::Mut(4));
+    local_send(&root, LocalMailboxEntry::Mut(0));
+
+    // the mut message waits for the guard held by the spawned task, and the end
+    // value is produced once the actor is deleted
+    assert_eq!(local::run(root), Ok(7));
+    assert_eq!(*log.borrow(), vec![
+        "mut 1", "shared 2", "shared 0", "shared 3", "release", "mut 4", "mut 0", "drop",
+    ]);
+}
+
+#[test]
+fn test_local_drop() {
+    use crate::local::LocalMailboxEntry;
+
+    init_log();
+
+    // dropping the root actor kills the actor, which drops its state
+    let log = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let root = local::LocalRootActor::new(LocalRecorder { log: log.clone() });
+    let mailbox = root.mailbox().clone();
+    mailbox.send(LocalMailboxEntry::Mut(1));
+    drop(root);
+    assert_eq!(*log.borrow(), vec!["drop"]);
+
+    // and messages sent to it are discarded
+    mailbox.send(LocalMailboxEntry::Mut(2));
+    assert_eq!(*log.borrow(), vec!["drop"]);
+}
+
+// wait for a tree running on other threads to record a number of events
+fn await_events(log: &Arc<Mutex<Vec<String>>>, n: usize) {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while events(log).len() < n {
+        assert!(std::time::Instant::now() < deadline, "actors did not run");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_spawn_blocking() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    // blocking subordinates run on their own threads
+    let (mut mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::SpawnBlocking("a"))).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Thread)).is_ok());
+    exec.run_until_idle();
+    await_events(&log, 2);
+    let mut log_sorted = events(&log);
+    log_sorted.sort();
+    assert_eq!(log_sorted[0], "a on reflex-blocking");
+    assert!(log_sorted[1].starts_with("root on "));
+    assert_ne!(log_sorted[1], "root on reflex-blocking");
+
+    // as do blocking roots
+    log.lock().unwrap().clear();
+    let (mut mailbox, end) = system.spawn_root_blocking(Node::new("blocker", &log, None));
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::Thread)).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+    assert_eq!(end.wait(), Ok("blocker"));
+    assert_eq!(events(&log), vec!["blocker on reflex-blocking", "drop blocker"]);
+}
+
+#[test]
+fn test_blocking_pool_spawner() {
+    init_log();
+
+    run_tree(spawn::blocking::BlockingPool::new(2));
+}
+
+#[test]
+fn test_blocking_pool_limit() {
+    use std::sync::mpsc;
+
+    init_log();
+
+    // a task which blocks its thread until it is released
+    fn blocker(release: mpsc::Receiver<()>, done: mpsc::Sender<()>) -> spawn::BoxedTask {
+        Box::new(futures::future::lazy(move || {
+            release.recv().unwrap();
+            done.send(()).unwrap();
+            Ok(())
+        }))
+    }
+
+    let pool = spawn::blocking::BlockingPool::new(1);
+    let (done_send, done_recv) = mpsc::channel();
+    let (release_send, release_recv) = mpsc::channel();
+    assert!(pool.spawn(blocker(release_recv, done_send.clone())).is_ok());
+
+    // once every thread is occupied, tasks are refused rather than queued
+    let (release_send_2, release_recv_2) = mpsc::channel();
+    let refused = pool.spawn(blocker(release_recv_2, done_send.clone()));
+    assert!(refused.is_err());
+
+    // and accepted again once a thread is free
+    release_send.send(()).unwrap();
+    assert_eq!(done_recv.recv_timeout(Duration::from_secs(5)), Ok(()));
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let mut task = refused.err().unwrap();
+    while let Err(refused) = pool.spawn(task) {
+        assert!(std::time::Instant::now() < deadline, "thread was not freed");
+        std::thread::sleep(Duration::from_millis(1));
+        task = refused;
+    }
+    release_send_2.send(()).unwrap();
+    assert_eq!(done_recv.recv_timeout(Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn test_blocking_pool_panic() {
+    init_log();
+
+    // an actor which panics frees its thread, for the pool's next task
+    let system = ActorSystem::new(spawn::blocking::BlockingPool::new(1));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut a, a_end) = system.spawn_root(Node::new("a", &log, None));
+    assert!(a.send_now(MailboxEntry::Mut(NodeMut::Panic)).is_ok());
+    assert!(a_end.wait().is_err());
+
+    // the panicked task may still be counted until its thread loops
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let (mut b, b_end) = system.spawn_root(Node::new("b", &log, None));
+        assert!(b.send_now(MailboxEntry::Mut(NodeMut::End)).is_ok());
+        match b_end.wait() {
+            Ok(end) => {
+                assert_eq!(end, "b");
+                break;
+            },
+            Err(_) => assert!(std::time::Instant::now() < deadline, "thread was not freed"),
+        };
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_send_blocking() {
+    use crate::mailbox::SendBlockingError;
+
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (mut mailbox, _end) = system.spawn_root(Node::new("root", &log, None));
+    let (mut blocker, _blocker_end) = system.spawn_root_blocking(Node::new("blocker", &log, None));
+
+    // sends block outside of tasks, and on the threads of blocking actors
+    let target = mailbox.clone().downgrade();
+    assert!(blocker.send_blocking(MailboxEntry::Mut(NodeMut::SendBlocking(target))).is_ok());
+    await_events(&log, 1);
+    exec.run_until_idle();
+    assert_eq!(events(&log)[..], ["blocker sent true", "root on internal::test::test_send_blocking"]);
+
+    // but not from within executor tasks, which they could deadlock
+    let target = mailbox.clone().downgrade();
+    assert!(mailbox.send_now(MailboxEntry::Mut(NodeMut::SendBlocking(target))).is_ok());
+    exec.run_until_idle();
+    assert_eq!(events(&log)[2..], ["root sent false"]);
+
+    // a send to a full mailbox times out
+    while mailbox.send_now(MailboxEntry::Mut(NodeMut::Identify)).is_ok() {}
+    let sent = mailbox.send_blocking_timeout(MailboxEntry::Mut(NodeMut::Identify), Duration::from_millis(10));
+    assert!(matches!(sent, Err(SendBlockingError::Timeout(_))));
+}
+
+#[test]
+fn test_clock_timers() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let start = exec.clock().now();
+    let fired = Arc::new(Mutex::new(Vec::new()));
+
+    // a timer which, once it fires, registers another
+    let fired_2 = fired.clone();
+    let fired_3 = fired.clone();
+    let task = Delay::new(start + Duration::from_secs(1))
+        .and_then(move |()| {
+            fired_2.lock().unwrap().push(1);
+            Delay::new(start + Duration::from_secs(3))
+        })
+        .map(move |()| fired_3.lock().unwrap().push(3));
+    exec.spawn(Box::new(task)).ok().unwrap();
+    let fired_2 = fired.clone();
+    let task = Delay::new(start + Duration::from_secs(2))
+        .map(move |()| fired_2.lock().unwrap().push(2));
+    exec.spawn(Box::new(task)).ok().unwrap();
+
+    exec.advance(Duration::from_millis(999));
+    assert!(fired.lock().unwrap().is_empty());
+    exec.advance(Duration::from_secs(5));
+    assert_eq!(*fired.lock().unwrap(), vec![1, 2, 3]);
+    assert_eq!(exec.clock().now(), start + Duration::from_millis(5999));
+}
+
+#[test]
+fn test_clock_delay_registration() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let deadline = exec.clock().now() + Duration::from_secs(1);
+    let polls = Arc::new(AtomicUsize::new(0));
+
+    // a task which polls its delay repeatedly, by notifying itself while it waits
+    let mut delay = Delay::new(deadline);
+    let polls_2 = polls.clone();
+    let task = futures::future::poll_fn(move || {
+        polls_2.fetch_add(1, Ordering::SeqCst);
+        let ready = delay.poll()?;
+        if ready.is_not_ready() && polls_2.load(Ordering::SeqCst) < 10 {
+            futures::task::current().notify();
         }
+        Ok(ready)
+    });
+    exec.spawn(Box::new(task)).ok().unwrap();
+
+    exec.run_until_idle();
+    assert_eq!(polls.load(Ordering::SeqCst), 10);
+    assert_eq!(exec.clock().pending(), 1);
+    exec.advance(Duration::from_secs(1));
+    assert_eq!(polls.load(Ordering::SeqCst), 11);
+    assert_eq!(exec.clock().pending(), 0);
+}
 
-        impl MessageUnion<Foo> for FooUnion {
-            type Shared = FooShared;
-            type Mut = FooMut;
+#[test]
+fn test_clock_dropped_delays() {
+    init_log();
+
+    // the deadlines of dropped delays do not pile up until they pass
+    let exec = TestExecutor::new();
+    let deadline = exec.clock().now() + Duration::from_secs(3600);
+    let task = futures::future::lazy(move || {
+        for _ in 0..1000 {
+            assert!(Delay::new(deadline).poll().unwrap().is_not_ready());
         }
+        Ok::<(), ()>(())
+    });
+    assert!(exec.block_on(task).is_ok());
+    assert!(exec.clock().pending() < 100);
+}
+
+#[test]
+fn test_clock_expiry() {
+    init_log();
 
-        let actor = Foo(RwLock::new(()));
-        let (
-            state,
-            mut send,
-        ) = create::create_actor(actor);
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
 
-        tokio::spawn(state);
+    // hold the actor in shared access, so that messages stay queued
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    exec.run_until_idle();
 
-        send.try_send(ActorMessage::Shared(smallvec![FooShared, FooShared, FooShared])).unwrap();
-        send.try_send(ActorMessage::Mut(FooMut)).unwrap();
-        send.try_send(ActorMessage::Shared(smallvec![FooShared])).unwrap();
+    let expiry = exec.clock().now() + Duration::from_secs(60);
+    assert!(mailbox.send_now_expiring(MailboxEntry::Mut(RecorderMut::Push(1)), expiry).is_ok());
+    // the time to live is measured from when the send is first polled by the executor
+    let mailbox_2 = mailbox.clone();
+    let send = futures::future::lazy(move || {
+        mailbox_2.send_with_ttl(MailboxEntry::Mut(RecorderMut::Push(2)), Duration::from_secs(10))
+    });
+    assert!(exec.block_on(send).is_ok());
+    // each message of an expired shared batch counts as expired
+    let expiry = exec.clock().now() + Duration::from_secs(20);
+    let batch = shared(vec![RecorderShared::TryPush(3), RecorderShared::TryPush(4)]);
+    assert!(mailbox.send_now_expiring(batch, expiry).is_ok());
 
-        Ok(())
-    }));
+    exec.advance(Duration::from_secs(30));
+    HELD.with(|held| held.borrow_mut().clear());
+    exec.run_until_idle();
+    assert_eq!(recorder_log(&exec, &mailbox), vec![1]);
+    assert_eq!(mailbox.metrics().expired(), 3);
+}
+
+#[test]
+fn test_dead_letters() {
+    init_log();
+
+    // the subscription receives the dead letters of concurrent tests too, so only
+    // those of this test's actor are checked
+    let mut letters = executor::spawn(manage::dead_letter::subscribe());
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    // expired, while the actor is held in shared access, with the shared batch
+    // queued behind the mutable message
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    exec.run_until_idle();
+    let expiry = exec.clock().now() + Duration::from_secs(1);
+    assert!(mailbox.send_now_expiring(MailboxEntry::Mut(RecorderMut::Push(1)), expiry).is_ok());
+    assert!(mailbox.send_now_expiring(shared(vec![RecorderShared::TryPush(1)]), expiry).is_ok());
+    exec.advance(Duration::from_secs(1));
+    HELD.with(|held| held.borrow_mut().clear());
+    exec.run_until_idle();
+
+    // still queued when the actor terminates, then sent after it terminated
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::End)).is_ok());
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(2))).is_ok());
+    assert_eq!(exec.block_on(end).ok(), Some(vec![]));
+    assert!(mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(3))).is_ok());
+
+    let mut received = Vec::new();
+    let notify = Arc::new(NoNotify);
+    while let Ok(Async::Ready(Some(letter))) = letters.poll_stream_notify(&notify, 0) {
+        if letter.actor == mailbox.id() {
+            received.push(letter);
+        }
+    }
+    let reasons: Vec<_> = received.iter()
+        .map(|letter| (letter.reason, letter.message_type))
+        .collect();
+    assert_eq!(reasons, vec![
+        (DeadLetterReason::Expired, std::any::type_name::<RecorderMut>()),
+        (DeadLetterReason::Expired, std::any::type_name::<RecorderShared>()),
+        (DeadLetterReason::Terminated, std::any::type_name::<RecorderMut>()),
+        (DeadLetterReason::ActorDead, std::any::type_name::<RecorderMut>()),
+    ]);
+    let pushed: Vec<_> = received[2..].iter()
+        .map(|letter| match letter.message.take().map(|msg| msg.downcast::<RecorderMut>()) {
+            Some(Ok(msg)) => match *msg {
+                RecorderMut::Push(n) => n,
+                _ => panic!("unexpected dead message"),
+            },
+            _ => panic!("dead message missing"),
+        })
+        .collect();
+    assert_eq!(pushed, vec![2, 3]);
+}
+
+#[test]
+fn test_clock_send_timeout() {
+    init_log();
+
+    let exec = TestExecutor::new();
+    let system = ActorSystem::new(exec.clone());
+    let (mut mailbox, _end) = system.spawn_root(Recorder { log: Vec::new() });
+
+    // fill the mailbox while the actor is held in shared access, and has already
+    // taken the message which it is blocked on
+    assert!(mailbox.send_now(shared(vec![RecorderShared::Hold])).is_ok());
+    exec.run_until_idle();
+    while mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(0))).is_ok() {}
+    exec.run_until_idle();
+    while mailbox.send_now(MailboxEntry::Mut(RecorderMut::Push(0))).is_ok() {}
+
+    let result = Arc::new(Mutex::new(None));
+    let result_2 = result.clone();
+    let deadline = exec.clock().now() + Duration::from_secs(5);
+    let send = mailbox.send_until(MailboxEntry::Mut(RecorderMut::Push(1)), deadline)
+        .then(move |sent| {
+            *result_2.lock().unwrap() = Some(sent.is_ok());
+            Ok(())
+        });
+    exec.spawn(Box::new(send)).ok().unwrap();
+
+    exec.advance(Duration::from_millis(4999));
+    assert_eq!(*result.lock().unwrap(), None);
+    exec.advance(Duration::from_millis(1));
+    assert_eq!(*result.lock().unwrap(), Some(false));
 }
-*/
\ No newline at end of file
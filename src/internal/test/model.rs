@@ -0,0 +1,254 @@
+use crate::internal::{ActorAccessStatus, ReleaseMode};
+use crate::internal::access::{AccessState, SharedRelease};
+use crate::internal::dispatch::{AccessSync, Observed, observe_release};
+use crate::manage::ActorPath;
+
+use loom::cell::UnsafeCell;
+use loom::sync::Arc;
+use loom::sync::atomic::{AtomicBool, Ordering};
+use loom::thread;
+
+/// The parts of an actor which the access protocol synchronizes.
+struct Model {
+    access: AccessState,
+    // stands in for the user state
+    state: UnsafeCell<Option<u32>>,
+    // stands in for notifying the dispatch task
+    routine_notified: AtomicBool,
+    // stands in for notifying the task of an upgrading guard
+    upgrade_notified: AtomicBool,
+}
+
+impl Model {
+    fn new(state: u32) -> Arc<Self> {
+        Arc::new(Model {
+            access: AccessState::new(),
+            state: UnsafeCell::new(Some(state)),
+            routine_notified: AtomicBool::new(false),
+            upgrade_notified: AtomicBool::new(false),
+        })
+    }
+
+    fn read(&self) -> Option<u32> {
+        self.state.with(|state| unsafe { *state })
+    }
+
+    fn write(&self, value: Option<u32>) {
+        self.state.with_mut(|state| unsafe { *state = value });
+    }
+
+    // release a shared guard, and notify whoever it requires
+    fn release_shared(&self) -> SharedRelease {
+        let release = self.access.release_shared();
+        match release {
+            SharedRelease::Other => (),
+            SharedRelease::Last => self.routine_notified.store(true, Ordering::SeqCst),
+            SharedRelease::UpgradeReady => self.upgrade_notified.store(true, Ordering::SeqCst),
+        };
+        release
+    }
+
+    // release an exclusive guard, and notify the routine
+    fn release_mut(&self) {
+        self.access.release_mut();
+        self.routine_notified.store(true, Ordering::SeqCst);
+    }
+
+    // observe the releases of guards, as the actor routine does when it is polled
+    fn observe(&self, access_status: &mut ActorAccessStatus) -> Observed {
+        observe_release(access_status, &self.access, &ActorPath::root())
+    }
+
+    // wait for the routine to be notified, as the dispatch task would, and consume
+    // the notification
+    fn wait_routine(&self) {
+        while !self.routine_notified.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        self.routine_notified.store(false, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn loom_mut_release() {
+    // the routine acquires the writes of a released exclusive guard
+    loom::model(|| {
+        let actor = Model::new(0);
+        let mut status = ActorAccessStatus::Exclusive;
+        actor.access.begin_mut();
+
+        let guard = {
+            let actor = actor.clone();
+            thread::spawn(move || {
+                actor.write(Some(1));
+                actor.release_mut();
+            })
+        };
+
+        actor.wait_routine();
+        assert!(matches!(actor.observe(&mut status), Observed::Proceed));
+        assert_eq!(status, ActorAccessStatus::Available);
+        assert_eq!(actor.read(), Some(1));
+
+        guard.join().unwrap();
+    });
+}
+
+#[test]
+fn loom_downgrade() {
+    // the routine begins new shared accesses after a downgrade, which read the
+    // writes of the guard from before it downgraded
+    loom::model(|| {
+        let actor = Model::new(0);
+        let mut status = ActorAccessStatus::Exclusive;
+        actor.access.begin_mut();
+
+        let guard = {
+            let actor = actor.clone();
+            thread::spawn(move || {
+                actor.write(Some(1));
+                actor.access.downgrade();
+                assert_eq!(actor.read(), Some(1));
+                actor.release_shared();
+            })
+        };
+
+        // spin until the downgrade or the release is observed
+        loop {
+            assert!(matches!(actor.observe(&mut status), Observed::Proceed));
+            if status != ActorAccessStatus::Exclusive {
+                break;
+            }
+            thread::yield_now();
+        }
+
+        // begin another shared access, unless the downgraded guard already released
+        match actor.access.begin_shared(status) {
+            Ok(()) => {
+                assert_eq!(actor.read(), Some(1));
+                actor.release_shared();
+            },
+            Err(_) => assert_eq!(actor.read(), Some(1)),
+        };
+
+        guard.join().unwrap();
+        assert_eq!(actor.access.load_access_count(), 0);
+    });
+}
+
+#[test]
+fn loom_delete() {
+    // the routine only observes a deletion once the deleting guard has released,
+    // and then acquires its removal of the user state
+    loom::model(|| {
+        let actor = Model::new(0);
+        let mut status = ActorAccessStatus::Exclusive;
+        actor.access.begin_mut();
+
+        let guard = {
+            let actor = actor.clone();
+            thread::spawn(move || {
+                actor.write(None);
+                actor.access.mark_deleted();
+                actor.release_mut();
+            })
+        };
+
+        loop {
+            match actor.observe(&mut status) {
+                Observed::Deleted => break,
+                Observed::Blocked => actor.wait_routine(),
+                Observed::Proceed => {
+                    // the deletion is not yet marked, so the guard must not have released
+                    assert_eq!(status, ActorAccessStatus::Exclusive);
+                    thread::yield_now();
+                },
+            };
+        }
+        assert_eq!(actor.read(), None);
+        assert_eq!(actor.access.swap_release_mode(ReleaseMode::Normal), ReleaseMode::Normal);
+
+        guard.join().unwrap();
+    });
+}
+
+#[test]
+fn loom_shared_clones() {
+    // exactly one of several concurrently released shared guards notifies the
+    // routine, after which the routine may write without racing their reads
+    loom::model(|| {
+        let actor = Model::new(1);
+        let mut status = ActorAccessStatus::Available;
+        actor.access.begin_shared(status).ok().unwrap();
+        status = ActorAccessStatus::Shared;
+        actor.access.clone_shared();
+
+        let guards: Vec<_> = (0..2)
+            .map(|_| {
+                let actor = actor.clone();
+                thread::spawn(move || {
+                    assert_eq!(actor.read(), Some(1));
+                    actor.release_shared() == SharedRelease::Last
+                })
+            })
+            .collect();
+
+        actor.wait_routine();
+        assert!(matches!(actor.observe(&mut status), Observed::Proceed));
+        assert_eq!(status, ActorAccessStatus::Available);
+        actor.access.begin_mut();
+        actor.write(Some(2));
+        actor.release_mut();
+
+        let lasts = guards.into_iter()
+            .map(|guard| guard.join().unwrap())
+            .filter(|&last| last)
+            .count();
+        assert_eq!(lasts, 1);
+    });
+}
+
+#[test]
+fn loom_upgrade() {
+    // a guard waiting to upgrade is notified by the release of the last other
+    // shared guard, and then may write without racing its reads, while the routine
+    // does not begin new shared accesses
+    loom::model(|| {
+        let actor = Model::new(1);
+        let status = ActorAccessStatus::Shared;
+        actor.access.begin_shared(ActorAccessStatus::Available).ok().unwrap();
+        actor.access.clone_shared();
+
+        let upgrading = {
+            let actor = actor.clone();
+            thread::spawn(move || {
+                assert!(actor.access.claim_upgrade());
+                if !actor.access.complete_upgrade() {
+                    while !actor.upgrade_notified.load(Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                    assert!(actor.access.complete_upgrade());
+                }
+                actor.write(Some(2));
+                actor.release_mut();
+            })
+        };
+        let other = {
+            let actor = actor.clone();
+            thread::spawn(move || {
+                assert_eq!(actor.read(), Some(1));
+                actor.release_shared();
+            })
+        };
+
+        // the routine may only begin a shared access before the upgrade is claimed
+        if actor.access.begin_shared(status).is_ok() {
+            assert!(actor.read().is_some());
+            actor.release_shared();
+        }
+
+        upgrading.join().unwrap();
+        other.join().unwrap();
+        assert_eq!(actor.access.load_access_count(), 0);
+    });
+}
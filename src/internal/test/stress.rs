@@ -0,0 +1,403 @@
+use super::init_log;
+use crate::*;
+use crate::msg_union::*;
+use crate::manage::AbnormalClose;
+use crate::mailbox::MailboxOwned;
+use crate::spawn::{Spawner, thread_pool::ThreadPool};
+use crate::util::lock;
+
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use smallvec::SmallVec;
+
+const ROUNDS: u64 = 16;
+const SENDERS: usize = 4;
+const MSGS_PER_SENDER: usize = 150;
+
+// added to the access count of the monitor while a mut access is in progress
+const WRITER: usize = 1 << 16;
+
+/// Xorshift generator, so that a failing round can be reproduced from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Identifies a message by its sender and its position in the sender's stream.
+#[derive(Copy, Clone, Debug)]
+struct Tag {
+    sender: usize,
+    seq: u64,
+    // the number of messages of the sender which must have finished their
+    // exclusive access before this message may begin, if it is shared
+    barrier: u64,
+}
+
+/// Records the accesses of the instrumented actor, outside of its state, so that
+/// overlapping accesses are observable rather than undefined.
+struct Monitor {
+    // the number of shared accesses, plus `WRITER` per mut access
+    access: AtomicUsize,
+    deleted: AtomicBool,
+    // per sender, the number of its messages which have completed
+    done: Vec<AtomicU64>,
+    // per sender, one past the sequence number of its last message which has
+    // finished its exclusive access
+    exclusive_done: Vec<AtomicU64>,
+    subordinate_ends: Mutex<Vec<u64>>,
+    violations: Mutex<Vec<String>>,
+}
+
+impl Monitor {
+    fn new() -> Arc<Self> {
+        Arc::new(Monitor {
+            access: AtomicUsize::new(0),
+            deleted: AtomicBool::new(false),
+            done: (0..SENDERS).map(|_| AtomicU64::new(0)).collect(),
+            exclusive_done: (0..SENDERS).map(|_| AtomicU64::new(0)).collect(),
+            subordinate_ends: Mutex::new(Vec::new()),
+            violations: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn violation(&self, violation: String) {
+        lock(&self.violations).push(violation);
+    }
+
+    fn check_alive(&self) {
+        if self.deleted.load(Ordering::SeqCst) {
+            self.violation("message processed after the actor was deleted".into());
+        }
+    }
+
+    fn enter_shared(&self) {
+        self.check_alive();
+        let previous = self.access.fetch_add(1, Ordering::SeqCst);
+        if previous >= WRITER {
+            self.violation("shared access began during a mut access".into());
+        }
+    }
+
+    fn exit_shared(&self) {
+        self.access.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn enter_mut(&self) {
+        self.check_alive();
+        let previous = self.access.fetch_add(WRITER, Ordering::SeqCst);
+        if previous != 0 {
+            self.violation(format!("mut access began during access {:#x}", previous));
+        }
+    }
+
+    fn exit_mut(&self) {
+        self.access.fetch_sub(WRITER, Ordering::SeqCst);
+    }
+
+    fn downgrade(&self) {
+        self.access.fetch_sub(WRITER - 1, Ordering::SeqCst);
+    }
+
+    fn upgrade(&self) {
+        let previous = self.access.fetch_add(WRITER - 1, Ordering::SeqCst);
+        if previous != 1 {
+            self.violation(format!("upgraded during access {:#x}", previous));
+        }
+    }
+
+    // check that the message began after the messages of its sender which it
+    // must follow
+    fn check_order(&self, tag: Tag, exclusive: bool) {
+        if exclusive {
+            let done = self.done[tag.sender].load(Ordering::SeqCst);
+            if done != tag.seq {
+                self.violation(format!("{:?} began after {} of its sender's messages completed", tag, done));
+            }
+        } else {
+            let exclusive_done = self.exclusive_done[tag.sender].load(Ordering::SeqCst);
+            if exclusive_done < tag.barrier {
+                self.violation(format!("{:?} began before its sender's exclusive message {}", tag, tag.barrier - 1));
+            }
+        }
+    }
+
+    fn finish_exclusive(&self, tag: Tag) {
+        self.exclusive_done[tag.sender].store(tag.seq + 1, Ordering::SeqCst);
+    }
+
+    fn complete(&self, tag: Tag) {
+        self.done[tag.sender].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// widen the window in which a concurrent access could overlap this one
+fn dawdle(tag: Tag) {
+    for _ in 0..tag.seq % 4 {
+        thread::yield_now();
+    }
+}
+
+/// Actor which reports its accesses to a monitor.
+struct Checker {
+    monitor: Arc<Monitor>,
+    pool: ThreadPool,
+    // keeps the subordinates alive until they delete themselves
+    children: Vec<MailboxOwned<ChildMsg>>,
+}
+
+#[derive(Copy, Clone)]
+struct CheckerMsg;
+
+impl MessageTypeUnion for CheckerMsg {
+    type SharedUnion = CheckerShared;
+    type MutUnion = CheckerMut;
+}
+
+enum CheckerShared {
+    Read(Tag),
+    // try to upgrade, and write if that succeeds
+    Upgrade(Tag),
+}
+
+enum CheckerMut {
+    Write(Tag),
+    // downgrade, and read
+    Downgrade(Tag),
+    // create a subordinate, which ends as soon as it processes a message
+    Spawn(Tag),
+    Delete,
+}
+
+impl Actor for Checker {
+    type Message = CheckerMsg;
+    type End = Result<(), AbnormalClose>;
+    type SubordinateEnd = u64;
+
+    fn handle_msg_shared(actor: ActorGuardShared<Self>, msg: CheckerShared) {
+        let monitor = actor.monitor.clone();
+        monitor.enter_shared();
+        match msg {
+            CheckerShared::Read(tag) => {
+                monitor.check_order(tag, false);
+                dawdle(tag);
+                monitor.complete(tag);
+                monitor.exit_shared();
+                drop(actor);
+            },
+            CheckerShared::Upgrade(tag) => {
+                monitor.check_order(tag, false);
+                match ActorGuardShared::try_upgrade(actor) {
+                    Ok(actor) => {
+                        monitor.upgrade();
+                        dawdle(tag);
+                        monitor.complete(tag);
+                        monitor.exit_mut();
+                        drop(actor);
+                    },
+                    Err(actor) => {
+                        dawdle(tag);
+                        monitor.complete(tag);
+                        monitor.exit_shared();
+                        drop(actor);
+                    },
+                };
+            },
+        };
+    }
+
+    fn handle_msg_mut(mut actor: ActorGuardMut<Self>, msg: CheckerMut) {
+        let monitor = actor.monitor.clone();
+        monitor.enter_mut();
+        match msg {
+            CheckerMut::Write(tag) => {
+                monitor.check_order(tag, true);
+                dawdle(tag);
+                monitor.finish_exclusive(tag);
+                monitor.complete(tag);
+                monitor.exit_mut();
+                drop(actor);
+            },
+            CheckerMut::Downgrade(tag) => {
+                monitor.check_order(tag, true);
+                monitor.finish_exclusive(tag);
+                // the monitor must leave the mut access before the actor does
+                monitor.downgrade();
+                let actor = ActorGuardMut::downgrade(actor);
+                dawdle(tag);
+                monitor.complete(tag);
+                monitor.exit_shared();
+                drop(actor);
+            },
+            CheckerMut::Spawn(tag) => {
+                monitor.check_order(tag, true);
+                let (child, mut mailbox, _) = actor.manage(Child);
+                actor.pool.spawn(Box::new(child)).ok().unwrap();
+                let id = (tag.sender as u64) << 32 | tag.seq;
+                assert!(mailbox.send_now(MailboxEntry::Mut(id)).is_ok());
+                actor.children.push(mailbox);
+                monitor.finish_exclusive(tag);
+                monitor.complete(tag);
+                monitor.exit_mut();
+                drop(actor);
+            },
+            CheckerMut::Delete => {
+                monitor.exit_mut();
+                monitor.deleted.store(true, Ordering::SeqCst);
+                ActorGuardMut::delete(actor, Ok(()));
+            },
+        };
+    }
+
+    fn handle_subordinate_end(actor: ActorGuardMut<Self>, id: u64) {
+        let monitor = actor.monitor.clone();
+        monitor.enter_mut();
+        let mut ends = lock(&monitor.subordinate_ends);
+        if ends.contains(&id) {
+            monitor.violation(format!("subordinate {:#x} ended twice", id));
+        }
+        ends.push(id);
+        drop(ends);
+        monitor.exit_mut();
+        drop(actor);
+    }
+}
+
+/// Subordinate which ends with the id it is sent.
+struct Child;
+
+#[derive(Copy, Clone)]
+struct ChildMsg;
+
+impl MessageTypeUnion for ChildMsg {
+    type SharedUnion = ();
+    type MutUnion = u64;
+}
+
+impl Actor for Child {
+    type Message = ChildMsg;
+    type End = u64;
+    type SubordinateEnd = ();
+
+    fn handle_msg_shared(_: ActorGuardShared<Self>, _: ()) {}
+
+    fn handle_msg_mut(actor: ActorGuardMut<Self>, id: u64) {
+        ActorGuardMut::delete(actor, id);
+    }
+
+    fn handle_subordinate_end(_: ActorGuardMut<Self>, _: ()) {}
+}
+
+/// Generate a random stream of messages for one sender, and send it.
+///
+/// Returns the number of tagged messages sent.
+fn send_stream(
+    mut mailbox: MailboxOwned<CheckerMsg>,
+    sender: usize,
+    seed: u64,
+    delete_at: Option<usize>,
+) -> u64 {
+    let mut rng = Rng(seed);
+    let mut seq = 0;
+    let mut barrier = 0;
+    for i in 0..MSGS_PER_SENDER {
+        if delete_at == Some(i) {
+            assert!(mailbox.send_blocking(MailboxEntry::Mut(CheckerMut::Delete)).is_ok());
+            break;
+        }
+        let mut tag = || {
+            let tag = Tag { sender, seq, barrier };
+            seq += 1;
+            tag
+        };
+        let entry = match rng.below(16) {
+            0..=6 => {
+                let batch = (0..1 + rng.below(4))
+                    .map(|_| match rng.below(4) {
+                        0 => CheckerShared::Upgrade(tag()),
+                        _ => CheckerShared::Read(tag()),
+                    })
+                    .collect::<SmallVec<_>>();
+                MailboxEntry::Shared(batch)
+            },
+            7..=11 => MailboxEntry::Mut(CheckerMut::Write(tag())),
+            12..=14 => MailboxEntry::Mut(CheckerMut::Downgrade(tag())),
+            _ => MailboxEntry::Mut(CheckerMut::Spawn(tag())),
+        };
+        if let MailboxEntry::Mut(_) = entry {
+            barrier = seq;
+        }
+        assert!(mailbox.send_blocking(entry).is_ok());
+    }
+    seq
+}
+
+#[test]
+fn test_stress_dispatch_order() {
+    init_log();
+
+    let pool = ThreadPool::new(4);
+    for round in 0..ROUNDS {
+        let seed = round.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut rng = Rng(seed);
+        // in some rounds, a sender deletes the actor partway through its stream
+        let delete_at = match rng.below(4) {
+            0 => Some(rng.below(MSGS_PER_SENDER as u64) as usize),
+            _ => None,
+        };
+
+        let monitor = Monitor::new();
+        let root = RootActor::new(Checker {
+            monitor: monitor.clone(),
+            pool: pool.clone(),
+            children: Vec::new(),
+        }).parallel_shared(pool.clone());
+        let mut mailbox = root.mailbox().clone();
+        let (end_send, end_recv) = mpsc::channel();
+        let task = root.then(move |end| {
+            let _ = end_send.send(end);
+            Ok(())
+        });
+        pool.spawn(Box::new(task)).ok().unwrap();
+
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|sender| {
+                let mailbox = mailbox.clone();
+                let seed = rng.next();
+                let delete_at = delete_at.filter(|_| sender == 0);
+                thread::spawn(move || send_stream(mailbox, sender, seed, delete_at))
+            })
+            .collect();
+        let sent: Vec<u64> = senders.into_iter()
+            .map(|sender| sender.join().unwrap())
+            .collect();
+        assert!(mailbox.send_blocking(MailboxEntry::Mut(CheckerMut::Delete)).is_ok());
+        drop(mailbox);
+
+        let end = end_recv.recv_timeout(Duration::from_secs(10))
+            .unwrap_or_else(|_| panic!("round with seed {:#x} did not end", seed));
+        assert!(end.is_ok(), "round with seed {:#x} ended abnormally", seed);
+
+        let violations = lock(&monitor.violations).clone();
+        assert!(violations.is_empty(), "round with seed {:#x}: {:#?}", seed, violations);
+        if delete_at.is_none() {
+            // the final deletion was queued after every sender's messages
+            for (sender, &sent) in sent.iter().enumerate() {
+                assert_eq!(monitor.done[sender].load(Ordering::SeqCst), sent, "round with seed {:#x}", seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use super::queue::{SystemSend, MsgQueueEntry};
+use crate::Actor;
+use crate::manage::{ActorId, ExitReason};
+use crate::msg_union::{MessageTypeUnion, MailboxEntry};
+use crate::util::lock;
+use crate::util::drop_signal::DropSignalArcWeak;
+
+use std::mem;
+use std::sync::{Arc, Weak, Mutex};
+use std::collections::BTreeMap;
+
+/// Callback which delivers an exit reason to a watching actor.
+pub type ExitCallback = Box<dyn FnOnce(ExitReason) + Send>;
+
+/// Function with which an actor which traps exits converts the exits of linked
+/// actors into messages.
+///
+/// This is reference counted, so that it can be called without holding the lock
+/// in which it is stored.
+pub type ExitTrap<Act> = Arc<
+    dyn Fn(ActorId, ExitReason) -> <<Act as Actor>::Message as MessageTypeUnion>::MutUnion
+    + Send
+    + Sync
+>;
+
+/// Key of a watcher's callback.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Watcher {
+    /// An actor watching through `watch`.
+    Watch(ActorId),
+    /// An actor linked through `link`.
+    Link(ActorId),
+}
+
+/// The actors watching for an actor's termination.
+///
+/// This is shared by the actor and all of its mailboxes, so that an actor may
+/// be watched, or linked to, through any mailbox to it.
+pub struct ExitWatchers {
+    state: Mutex<WatchState>,
+    // the actor's own end of its links
+    target: Arc<dyn ExitTarget>,
+    // the watchers of the actors which this actor watches or links to, keyed by
+    // the other actor's id and our key in its watchers, so that we can remove our
+    // callbacks from them once this actor exits
+    watching: Mutex<BTreeMap<(ActorId, Watcher), Weak<ExitWatchers>>>,
+}
+
+enum WatchState {
+    Alive(BTreeMap<Watcher, ExitCallback>),
+    Exited(ExitReason),
+}
+
+impl ExitWatchers {
+    pub fn new(target: Arc<dyn ExitTarget>) -> Self {
+        ExitWatchers {
+            state: Mutex::new(WatchState::Alive(BTreeMap::new())),
+            target,
+            watching: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The actor's own end of its links, to be notified of linked actors' exits.
+    pub fn target(&self) -> Arc<dyn ExitTarget> {
+        self.target.clone()
+    }
+
+    /// Register a watcher, replacing its previous callback, if any.
+    ///
+    /// If the actor already terminated, the callback is invoked immediately.
+    pub fn watch(&self, watcher: Watcher, callback: ExitCallback) {
+        let mut state = lock(&self.state);
+        match *state {
+            WatchState::Alive(ref mut watchers) => {
+                watchers.insert(watcher, callback);
+            },
+            WatchState::Exited(reason) => {
+                drop(state);
+                callback(reason);
+            },
+        }
+    }
+
+    /// Unregister a watcher.
+    pub fn unwatch(&self, watcher: Watcher) {
+        if let WatchState::Alive(ref mut watchers) = *lock(&self.state) {
+            watchers.remove(&watcher);
+        }
+    }
+
+    /// Record that this actor registered a callback in another actor's watchers,
+    /// under the given key, so that it is removed once this actor exits.
+    ///
+    /// If this actor already exited, the callback is removed immediately.
+    pub fn watching(&self, other: ActorId, watcher: Watcher, watchers: &Arc<ExitWatchers>) {
+        if self.exited() {
+            watchers.unwatch(watcher);
+            return;
+        }
+        let mut watching = lock(&self.watching);
+        // forget the actors which already exited, and so have no callbacks left
+        watching.retain(|_, watchers| match watchers.upgrade() {
+            Some(watchers) => !watchers.exited(),
+            None => false,
+        });
+        watching.insert((other, watcher), Arc::downgrade(watchers));
+    }
+
+    /// Forget a callback in another actor's watchers, once it has been removed.
+    pub fn not_watching(&self, other: ActorId, watcher: Watcher) {
+        lock(&self.watching).remove(&(other, watcher));
+    }
+
+    /// Notify all watchers that the actor terminated, and remove this actor's
+    /// callbacks from the watchers of the actors which it watches.
+    ///
+    /// Only the first call has an effect.
+    pub fn exit(&self, reason: ExitReason) {
+        let previous = {
+            let mut state = lock(&self.state);
+            match *state {
+                WatchState::Alive(_) => mem::replace(&mut *state, WatchState::Exited(reason)),
+                WatchState::Exited(_) => return,
+            }
+        };
+        let watching = mem::take(&mut *lock(&self.watching));
+        for ((_, watcher), watchers) in watching {
+            if let Some(watchers) = watchers.upgrade() {
+                watchers.unwatch(watcher);
+            }
+        }
+        if let WatchState::Alive(watchers) = previous {
+            for callback in watchers.into_values() {
+                callback(reason);
+            }
+        }
+    }
+
+    fn exited(&self) -> bool {
+        matches!(*lock(&self.state), WatchState::Exited(_))
+    }
+
+    /// The number of callbacks registered, for tests.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        match *lock(&self.state) {
+            WatchState::Alive(ref watchers) => watchers.len(),
+            WatchState::Exited(_) => 0,
+        }
+    }
+}
+
+/// An actor's end of its links, type-erased.
+pub trait ExitTarget: Send + Sync {
+    /// Handle the exit of a linked actor.
+    fn linked_exit(&self, from: ActorId, reason: ExitReason);
+}
+
+/// An actor's end of its links.
+///
+/// Unless the actor traps exits, an abnormal exit of a linked actor kills it.
+pub struct LinkedExits<Act: Actor> {
+    kill_signal: DropSignalArcWeak,
+    system_send: SystemSend<Act>,
+    trap: Mutex<Option<ExitTrap<Act>>>,
+}
+
+impl<Act: Actor> LinkedExits<Act> {
+    pub fn new(kill_signal: DropSignalArcWeak, system_send: SystemSend<Act>) -> Self {
+        LinkedExits {
+            kill_signal,
+            system_send,
+            trap: Mutex::new(None),
+        }
+    }
+
+    /// Set or clear the function with which exits are trapped.
+    pub fn set_trap(&self, trap: Option<ExitTrap<Act>>) {
+        *lock(&self.trap) = trap;
+    }
+}
+
+impl<Act: Actor> ExitTarget for LinkedExits<Act> {
+    fn linked_exit(&self, from: ActorId, reason: ExitReason) {
+        // the trap is user code, which must not run while we hold the lock
+        let trap = lock(&self.trap).clone();
+        match trap {
+            Some(trap) => {
+                let entry = MsgQueueEntry::MailboxEntry(MailboxEntry::Mut(trap(from, reason)), None);
+                if self.system_send.unbounded_send(entry).is_err() {
+                    trace!("linked actor {} exited, but trapping actor is already dead", from);
+                }
+            },
+            None if reason.is_abnormal() => {
+                trace!("linked actor {} exited abnormally, killing actor", from);
+                self.kill_signal.send();
+            },
+            None => (),
+        }
+    }
+}
@@ -8,6 +8,14 @@ extern crate atomic;
 extern crate smallvec;
 #[cfg(feature = "failure-interop")]
 extern crate failure;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "async-std")]
+extern crate async_std;
+#[cfg(feature = "async-std")]
+extern crate futures03;
+#[cfg(loom)]
+extern crate loom;
 
 use crate::msg_union::{Message, MessageTypeUnion};
 
@@ -26,12 +34,35 @@ pub mod mailbox;
 /// Actor management API.
 pub mod manage;
 
+/// Spawning of tasks onto executors.
+pub mod spawn;
+
+/// Actors which are not `Send`, run on a single thread.
+pub mod local;
+
+/// Deterministic testing of actors, under the `testing` feature.
+///
+/// The feature is meant only for tests, so it should be enabled through
+/// dev-dependencies, by a crate which uses resolver 2, so that it is not enabled
+/// in normal builds too.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 // re-export actor guards to the crate root
 #[doc(inline)]
-pub use crate::internal::{ActorGuardShared, ActorGuardMut};
+pub use crate::internal::{
+    ActorGuardShared,
+    ActorGuardMut,
+    ActorGuardUpgrade,
+    MappedActorGuardShared,
+    MappedActorGuardMut,
+};
 
 pub use crate::manage::RootActor;
 
+#[cfg(feature = "thread-pool")]
+pub use crate::spawn::thread_pool::run;
+
 /// Actor types.
 ///
 /// This trait is meant to be implemented with macros.
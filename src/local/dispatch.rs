@@ -0,0 +1,170 @@
+use super::*;
+use crate::internal::dispatch::{
+    AccessSync,
+    Observed,
+    SharedBlocked,
+    observe_release,
+    may_access,
+    shared_blocked_by,
+};
+
+use std::hint::unreachable_unchecked;
+
+use futures::try_ready;
+use futures::task;
+use futures::Stream;
+
+/// A local actor state is, itself, the dispatch future implementation for the
+/// dispatch task.
+///
+/// This follows the same protocol as the dispatch routine of an `Actor`, but since
+/// the actor's guards can only be released on this thread, it needs no atomics.
+impl<Act: LocalActor> Future for LocalActorState<Act> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            // observe the releases of our actor guards
+            match observe_release(&mut self.access_status, &*self.shared, &self.shared.path) {
+                Observed::Proceed => (),
+                Observed::Blocked => return Ok(Async::NotReady),
+                Observed::Deleted => {
+                    trace!("{}: local actor deleted, terminating routine", self.shared.path);
+                    return Ok(Async::Ready(()));
+                },
+            };
+
+            // process the rest of the current shared batch before taking another message
+            if let Some(mut batch) = self.curr_batch.take() {
+                if let Some(blocked) = shared_blocked_by(
+                    self.shared.access_count.get(),
+                    self.access_status,
+                ) {
+                    self.curr_batch = Some(batch);
+                    match blocked {
+                        SharedBlocked::Upgrade => {
+                            trace!("{}: shared actor guard has upgraded, actor blocked", self.shared.path);
+                            self.access_status = ActorAccessStatus::Exclusive;
+                            return Ok(Async::NotReady);
+                        },
+                        SharedBlocked::Stale => continue,
+                    }
+                }
+
+                // batches are never left empty
+                let msg = match batch.next() {
+                    Some(msg) => msg,
+                    None => unreachable!("empty shared batch"),
+                };
+                if batch.len() > 0 {
+                    self.curr_batch = Some(batch);
+                }
+
+                let guard = self.acquire_guard_shared();
+                Act::handle_msg_shared(guard, msg);
+                continue;
+            }
+
+            // attempt to grab a message to possibly process
+            if self.curr_msg.is_none() {
+                self.curr_msg = try_ready!(self.msg_recv.poll());
+            }
+
+            // exit with completion if the mailbox is empty and dropped
+            let msg = match self.curr_msg.take() {
+                Some(msg) => msg,
+                None => {
+                    trace!("{}: no more messages, local actor terminating", self.shared.path);
+                    return Ok(Async::Ready(()));
+                },
+            };
+
+            // yield as not-ready if the message access type isn't compatible with our
+            // current access status
+            if !may_access(msg.is_shared(), self.access_status) {
+                trace!("{}: message access type is not compatible with actor access status, actor blocked", self.shared.path);
+                self.curr_msg = Some(msg);
+                return Ok(Async::NotReady);
+            }
+
+            // update synchronization state, and create an actor guard
+            // then pass the guard and message to user code
+            match msg {
+                LocalMailboxEntry::Shared(msg_vec) => {
+                    // the batch is processed one message at a time, so that upgrades
+                    // are observed between its messages
+                    if !msg_vec.is_empty() {
+                        self.curr_batch = Some(msg_vec.into_iter());
+                    }
+                },
+                LocalMailboxEntry::Mut(msg) => {
+                    let guard = self.acquire_guard_mut();
+                    Act::handle_msg_mut(guard, msg);
+                },
+            };
+
+            // loop until we terminate or block
+        }
+    }
+}
+
+impl<Act: LocalActor> AccessSync for LocalStateShared<Act> {
+    fn load_access_count(&self) -> u32 {
+        self.access_count.get()
+    }
+
+    fn swap_release_mode(&self, mode: ReleaseMode) -> ReleaseMode {
+        self.release_mode.replace(mode)
+    }
+}
+
+// helper functions to be called from <LocalActorState as Future>::poll
+impl<Act: LocalActor> LocalActorState<Act> {
+    fn acquire_guard_shared(&mut self) -> LocalGuardShared<Act> {
+        trace!("{}: beginning shared actor access", self.shared.path);
+
+        let shared_state = Rc::clone(&self.shared);
+        shared_state.access_count.set(shared_state.access_count.get() + 1);
+        let dispatch_task = task::current();
+
+        self.access_status = ActorAccessStatus::Shared;
+        // create a shared alias into the user_state unsafe cell
+        let ptr: *const Act = unsafe {
+            let ptr: &Option<Act> = &*shared_state.user_state.get();
+            match *ptr {
+                Some(ref act) => act as *const Act,
+                None => unreachable_unchecked()
+            }
+        };
+        LocalGuardShared {
+            shared_state,
+            dispatch_task,
+            ptr,
+        }
+    }
+
+    fn acquire_guard_mut(&mut self) -> LocalGuardMut<Act> {
+        trace!("{}: beginning exclusive actor access", self.shared.path);
+
+        let shared_state = Rc::clone(&self.shared);
+        let previous_access_count = shared_state.access_count.replace(1);
+        debug_assert_eq!(previous_access_count, 0);
+        let dispatch_task = task::current();
+
+        self.access_status = ActorAccessStatus::Exclusive;
+        // create a unique alias into the user_state unsafe cell
+        let ptr: *mut Act = unsafe {
+            let ptr: &mut Option<Act> = &mut *shared_state.user_state.get();
+            match *ptr {
+                Some(ref mut act) => act as *mut Act,
+                None => unreachable_unchecked()
+            }
+        };
+        LocalGuardMut {
+            shared_state,
+            dispatch_task,
+            ptr,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use super::{LocalActor, LocalRootActor};
+use crate::{
+    manage::{AbnormalClose, IntoResult},
+    util::lock,
+};
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread::{self, Thread},
+};
+
+use futures::{
+    Future,
+    Async,
+    executor::{self, Spawn, Notify, NotifyHandle},
+};
+
+/// A future which has been spawned onto the local executor.
+type LocalTask = Spawn<Box<dyn Future<Item = (), Error = ()>>>;
+
+thread_local! {
+    // the tasks spawned onto the executor running on this thread, if any, which
+    // the executor has yet to take
+    static SPAWNED: RefCell<Option<Vec<LocalTask>>> = const { RefCell::new(None) };
+}
+
+// the notify id of the future which the executor is blocking on
+const ROOT: usize = usize::MAX;
+
+/// Run a local actor on the current thread, blocking until it resolves to its
+/// end value.
+///
+/// Tasks spawned with `reflex::local::spawn` while the actor runs are run on the
+/// current thread as well, and are dropped once the actor resolves.
+pub fn run<Act>(root: LocalRootActor<Act>) -> Result<
+    <Act::End as IntoResult>::Item,
+    <Act::End as IntoResult>::Error,
+>
+where
+    Act: LocalActor,
+    Act::End: IntoResult,
+    <Act::End as IntoResult>::Error: From<AbnormalClose>,
+{
+    block_on(root)
+}
+
+/// Spawn a task onto the local executor of the current thread, which may hold
+/// local actor guards.
+///
+/// # Panics
+///
+/// Panics if not called from within `reflex::local::run`.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Item = (), Error = ()> + 'static,
+{
+    let task: Box<dyn Future<Item = (), Error = ()>> = Box::new(future);
+    SPAWNED.with(|spawned| match *spawned.borrow_mut() {
+        Some(ref mut spawned) => spawned.push(executor::spawn(task)),
+        None => panic!("reflex::local::spawn called outside of reflex::local::run"),
+    });
+}
+
+// queue of the ids of the notified tasks of an executor
+struct ReadyQueue {
+    ready: Mutex<VecDeque<usize>>,
+    thread: Thread,
+}
+
+impl Notify for ReadyQueue {
+    fn notify(&self, id: usize) {
+        lock(&self.ready).push_back(id);
+        self.thread.unpark();
+    }
+}
+
+/// Run a future, and the tasks spawned while it runs, to completion of the future.
+fn block_on<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    let ready = Arc::new(ReadyQueue {
+        ready: Mutex::new(VecDeque::from(vec![ROOT])),
+        thread: thread::current(),
+    });
+    let notify = NotifyHandle::from(ready.clone());
+    let mut root = executor::spawn(future);
+    let mut tasks: Vec<Option<LocalTask>> = Vec::new();
+
+    // restore the executor which this one is nested in, if any, once done
+    let outer = SPAWNED.with(|spawned| spawned.borrow_mut().replace(Vec::new()));
+    let result = loop {
+        // take on the newly spawned tasks
+        let spawned = SPAWNED.with(|spawned| match *spawned.borrow_mut() {
+            Some(ref mut spawned) => spawned.split_off(0),
+            None => Vec::new(),
+        });
+        for task in spawned {
+            let id = match tasks.iter().position(Option::is_none) {
+                Some(id) => id,
+                None => {
+                    tasks.push(None);
+                    tasks.len() - 1
+                },
+            };
+            tasks[id] = Some(task);
+            lock(&ready.ready).push_back(id);
+        }
+
+        // the queue must not be locked while a task runs, since it may notify
+        let id = lock(&ready.ready).pop_front();
+        match id {
+            Some(ROOT) => match root.poll_future_notify(&notify, ROOT) {
+                Ok(Async::NotReady) => (),
+                Ok(Async::Ready(item)) => break Ok(item),
+                Err(e) => break Err(e),
+            },
+            Some(id) => {
+                let done = match tasks.get_mut(id) {
+                    Some(&mut Some(ref mut task)) => !matches!(
+                        task.poll_future_notify(&notify, id),
+                        Ok(Async::NotReady)
+                    ),
+                    // a stale notification of a completed task
+                    _ => false,
+                };
+                if done {
+                    tasks[id] = None;
+                }
+            },
+            None => {
+                let idle = SPAWNED.with(|spawned| spawned.borrow()
+                    .as_ref()
+                    .map_or(true, Vec::is_empty));
+                if idle {
+                    thread::park();
+                }
+            },
+        }
+    };
+    SPAWNED.with(|spawned| *spawned.borrow_mut() = outer);
+    result
+}
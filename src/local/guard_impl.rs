@@ -0,0 +1,178 @@
+use super::{LocalActor, LocalGuardShared, LocalGuardMut};
+use crate::internal::{ReleaseMode, UPGRADED, ACCESS_COUNT_MASK};
+use crate::manage::{ActorId, ActorPath};
+
+use std::ops::{Deref, DerefMut};
+use std::mem;
+use std::hint::unreachable_unchecked;
+
+impl<Act: LocalActor> LocalGuardMut<Act> {
+    /// Downgrade from mutable to shared.
+    ///
+    /// This unblocks the processing of further shared messages.
+    pub fn downgrade(guard: Self) -> LocalGuardShared<Act> {
+        guard.shared_state.release_mode.set(ReleaseMode::Downgrade);
+
+        // if this guard was upgraded from shared, it is no longer
+        let access_count = guard.shared_state.access_count.get();
+        guard.shared_state.access_count.set(access_count & !UPGRADED);
+
+        // notify task so that more messages can be processed
+        guard.dispatch_task.notify();
+
+        // like the guards of `Actor`s, these have the same repr(C) representation
+        unsafe {
+            mem::transmute::<
+                LocalGuardMut<Act>,
+                LocalGuardShared<Act>,
+            >(guard)
+        }
+    }
+
+    /// Delete this actor, and extract the inner state.
+    pub fn delete(guard: Self, end: Act::End) -> Act {
+        // send the end message to the root handle
+        guard.shared_state.send_end(end);
+
+        // extract user state
+        // be careful, because this invalidates our internal ptr
+        let user_state: Act = unsafe {
+            let cell: *mut Option<Act> = guard.shared_state.user_state.get();
+            match (*cell).take() {
+                Some(user_state) => user_state,
+                None => unreachable_unchecked(),
+            }
+        };
+
+        // if this release mode isn't set, the old memory for the user state
+        // may be invalidly accessed
+        guard.shared_state.release_mode.set(ReleaseMode::Delete);
+
+        // the dropping of self will handle task notification
+        mem::drop(guard);
+
+        user_state
+    }
+
+    /// The unique id of this actor.
+    pub fn id(&self) -> ActorId {
+        self.shared_state.id
+    }
+
+    /// The hierarchical path of this actor.
+    pub fn path(&self) -> &ActorPath {
+        &self.shared_state.path
+    }
+}
+
+impl<Act: LocalActor> LocalGuardShared<Act> {
+    /// Attempt to upgrade from shared to mutable.
+    ///
+    /// This only succeeds if this is the only shared guard to the actor. Otherwise,
+    /// the guard is returned.
+    pub fn try_upgrade(guard: Self) -> Result<LocalGuardMut<Act>, Self> {
+        if guard.shared_state.access_count.get() != 1 {
+            return Err(guard);
+        }
+        guard.shared_state.access_count.set(UPGRADED | 1);
+
+        // like with downgrading, we rely on the guards' repr(C)
+        let mut guard = unsafe {
+            mem::transmute::<
+                LocalGuardShared<Act>,
+                LocalGuardMut<Act>,
+            >(guard)
+        };
+
+        // re-derive the pointer, since the old one was a shared alias
+        guard.ptr = unsafe {
+            let cell: *mut Option<Act> = guard.shared_state.user_state.get();
+            match *cell {
+                Some(ref mut act) => act as *mut Act,
+                None => unreachable_unchecked(),
+            }
+        };
+
+        Ok(guard)
+    }
+
+    /// The unique id of this actor.
+    pub fn id(&self) -> ActorId {
+        self.shared_state.id
+    }
+
+    /// The hierarchical path of this actor.
+    pub fn path(&self) -> &ActorPath {
+        &self.shared_state.path
+    }
+}
+
+// == drop impls ==
+
+impl<Act: LocalActor> Drop for LocalGuardShared<Act> {
+    fn drop(&mut self) {
+        // decrement the access_count, and if we've lowered it to 0, notify the task
+        let access_count = self.shared_state.access_count.get() - 1;
+        self.shared_state.access_count.set(access_count);
+        if access_count == 0 {
+            trace!("{}: access count lowered to 0, notifying dispatch task", self.shared_state.path);
+            self.dispatch_task.notify();
+        }
+    }
+}
+
+impl<Act: LocalActor> Drop for LocalGuardMut<Act> {
+    fn drop(&mut self) {
+        // since we have exclusive access, this lowers the access count to 0
+        let previous_access_count = self.shared_state.access_count.replace(0);
+        debug_assert_eq!(previous_access_count & ACCESS_COUNT_MASK, 1);
+
+        trace!("{}: exclusive actor guard released, notifying dispatch task", self.shared_state.path);
+        self.dispatch_task.notify();
+    }
+}
+
+// implementation for cloning the shared actor guard
+impl<Act: LocalActor> Clone for LocalGuardShared<Act> {
+    fn clone(&self) -> Self {
+        // increment the access count, then the rest is straight-forward cloning
+        let access_count = self.shared_state.access_count.get();
+        self.shared_state.access_count.set(access_count + 1);
+
+        LocalGuardShared {
+            shared_state: self.shared_state.clone(),
+            dispatch_task: self.dispatch_task.clone(),
+            ptr: self.ptr,
+        }
+    }
+}
+
+// == deref impls ==
+
+impl<Act: LocalActor> Deref for LocalGuardShared<Act> {
+    type Target = Act;
+
+    fn deref(&self) -> &Act {
+        unsafe {
+            &*self.ptr
+        }
+    }
+}
+
+impl<Act: LocalActor> Deref for LocalGuardMut<Act> {
+    type Target = Act;
+
+    fn deref(&self) -> &Act {
+        unsafe {
+            &*self.ptr
+        }
+    }
+}
+
+impl<Act: LocalActor> DerefMut for LocalGuardMut<Act> {
+    fn deref_mut(&mut self) -> &mut Act {
+        unsafe {
+            &mut *self.ptr
+        }
+    }
+}
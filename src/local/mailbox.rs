@@ -0,0 +1,105 @@
+use super::LocalMessageTypeUnion;
+use crate::manage::{ActorId, ActorPath};
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use futures::unsync::mpsc;
+use smallvec::SmallVec;
+
+/// The runtime value which can be sent into a local mailbox.
+///
+/// This is the equivalent of `MailboxEntry`, for messages which need not be `Send`.
+pub enum LocalMailboxEntry<T: LocalMessageTypeUnion> {
+    /// The shared variant may store several messages, which are processed without
+    /// being interleaved with exclusively processed messages.
+    Shared(SmallVec<[<T as LocalMessageTypeUnion>::SharedUnion; 4]>),
+    Mut(<T as LocalMessageTypeUnion>::MutUnion),
+}
+
+impl<T: LocalMessageTypeUnion> LocalMailboxEntry<T> {
+    /// Whether this entry is processed with shared access.
+    pub(crate) fn is_shared(&self) -> bool {
+        matches!(*self, LocalMailboxEntry::Shared(_))
+    }
+}
+
+/// A handle for sending messages to a local actor, which keeps the actor alive.
+///
+/// Notable properties include:
+/// - this type is `Clone`, but not `Send`
+/// - the underlying channel is unbounded, so sending never blocks
+/// - if the actor is dead, sending into the mailbox will silently swallow the error
+///
+/// Mailboxes compare equal and hash by the identity of their actor.
+pub struct LocalMailbox<T: LocalMessageTypeUnion> {
+    sender: mpsc::UnboundedSender<LocalMailboxEntry<T>>,
+    id: ActorId,
+    path: ActorPath,
+}
+
+impl<T: LocalMessageTypeUnion> LocalMailbox<T> {
+    /// Crate-internal constructor.
+    pub (crate) fn new(
+        sender: mpsc::UnboundedSender<LocalMailboxEntry<T>>,
+        id: ActorId,
+        path: ActorPath,
+    ) -> Self {
+        LocalMailbox { sender, id, path }
+    }
+
+    /// The unique id of the actor.
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+
+    /// The hierarchical path of the actor.
+    pub fn path(&self) -> &ActorPath {
+        &self.path
+    }
+
+    /// Send a message to the actor.
+    ///
+    /// As usual, if the actor is dead, this will swallow that error.
+    pub fn send<Msg>(&self, message: Msg)
+        where
+            Msg: Into<LocalMailboxEntry<T>> {
+
+        if self.sender.unbounded_send(message.into()).is_err() {
+            trace!("{}: local mailbox send failure", self.path);
+        }
+    }
+}
+
+impl<T: LocalMessageTypeUnion> Clone for LocalMailbox<T> {
+    fn clone(&self) -> Self {
+        LocalMailbox {
+            sender: self.sender.clone(),
+            id: self.id,
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<T: LocalMessageTypeUnion> PartialEq for LocalMailbox<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: LocalMessageTypeUnion> Eq for LocalMailbox<T> {}
+
+impl<T: LocalMessageTypeUnion> Hash for LocalMailbox<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T: LocalMessageTypeUnion> fmt::Debug for LocalMailbox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalMailbox")
+            .field("id", &self.id)
+            .field("path", &self.path)
+            .finish()
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    internal::{ActorAccessStatus, ReleaseMode},
+    manage::{ActorId, ActorPath, AbnormalClose, IntoResult},
+};
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    rc::Rc,
+};
+
+use futures::{
+    {Future, Poll, Async},
+    task::Task,
+    unsync::{mpsc, oneshot},
+};
+
+/// Local actor dispatch task.
+mod dispatch;
+
+/// Local actor guard implementations.
+mod guard_impl;
+
+/// Handles for sending messages to local actors.
+pub mod mailbox;
+
+/// Executor which runs local actors on the current thread.
+pub mod executor;
+
+pub use self::mailbox::{LocalMailbox, LocalMailboxEntry};
+pub use self::executor::{run, spawn};
+
+/// Actor types which are not `Send`.
+///
+/// This is the equivalent of `Actor` for actors which hold thread-bound state,
+/// such as `Rc`s or GUI and FFI handles. A local actor is dispatched on the thread
+/// which created it, and its mailboxes and guards may not leave that thread.
+///
+/// Local actors do not have subordinates, and do not belong to an actor system.
+///
+/// This trait is meant to be implemented with macros.
+pub trait LocalActor: Sized + 'static {
+    /// Message types which this actor can process.
+    ///
+    /// This type is meant to be implemented with macros.
+    type Message: LocalMessageTypeUnion;
+
+    /// Type which signals the intentional termination of this actor.
+    ///
+    /// Terminating an actor through a `LocalGuardMut` requires the provision of an
+    /// instance of its end value.
+    type End: 'static;
+
+    fn handle_msg_shared(
+        actor: LocalGuardShared<Self>,
+        msg: <Self::Message as LocalMessageTypeUnion>::SharedUnion,
+    );
+
+    fn handle_msg_mut(
+        actor: LocalGuardMut<Self>,
+        msg: <Self::Message as LocalMessageTypeUnion>::MutUnion,
+    );
+}
+
+/// Local actor types which can process a particular message type with `&self`.
+pub trait LocalReactShared<Msg>: LocalActor + Sized {
+    fn process(actor: LocalGuardShared<Self>, message: Msg);
+}
+
+/// Local actor types which can process a particular message type with `&mut self`.
+pub trait LocalReactMut<Msg>: LocalActor + Sized {
+    fn process_mut(actor: LocalGuardMut<Self>, message: Msg);
+}
+
+/// Trait binding together a set of message types which a local actor can process,
+/// both shared and mut.
+///
+/// Unlike with `MessageTypeUnion`, the messages need not be `Send`.
+///
+/// Implementations are meant to be created with macros.
+///
+/// This type is never meant to be instantiated.
+pub trait LocalMessageTypeUnion: Sized + Copy + 'static {
+    /// Associated shared message union type.
+    type SharedUnion: 'static;
+
+    /// Associated mut message union type.
+    type MutUnion: 'static;
+}
+
+/// Implement LocalMessageTypeUnion for (), to help partially-written code compile.
+impl LocalMessageTypeUnion for () {
+    type SharedUnion = ();
+    type MutUnion = ();
+}
+
+/// Reflex's state for a local actor which is owned by the actor's dispatch routine.
+pub(crate) struct LocalActorState<Act: LocalActor> {
+    // handle to the shared state
+    shared: Rc<LocalStateShared<Act>>,
+
+    access_status: ActorAccessStatus,
+
+    // the message queue, and the slot for pushing a message back in
+    msg_recv: mpsc::UnboundedReceiver<LocalMailboxEntry<Act::Message>>,
+    curr_msg: Option<LocalMailboxEntry<Act::Message>>,
+    // the messages of the shared batch being processed which have yet to begin
+    curr_batch: Option<LocalSharedBatch<Act>>,
+}
+
+/// The remaining messages of a shared batch, which are processed one at a time.
+type LocalSharedBatch<Act> = smallvec::IntoIter<[<<Act as LocalActor>::Message as LocalMessageTypeUnion>::SharedUnion; 4]>;
+
+/// Reflex's state for a local actor which is reference counted.
+///
+/// This mirrors `ActorStateShared`, with cells in place of atomics.
+pub(crate) struct LocalStateShared<Act: LocalActor> {
+    // this actor's unique id
+    id: ActorId,
+    // this actor's hierarchical path
+    path: ActorPath,
+
+    // the user's actor struct, which we manually synchronize
+    // additionally, we make unsafe assumptions on when this is the Some variant
+    user_state: UnsafeCell<Option<Act>>,
+
+    // the current number of guards accessing (mutably or immutably) the user state
+    // the high bits of this are the `UPGRADED` flag
+    access_count: Cell<u32>,
+    // the way in which a guard released, like with `ActorStateShared`
+    release_mode: Cell<ReleaseMode>,
+
+    // channel to the root handle, for the end value
+    end_send: Cell<Option<oneshot::Sender<Act::End>>>,
+}
+
+/// Synchronization guard for shared (immutable) access to a local actor.
+///
+/// This type is notably `'static`, and clone-shareable, but not `Send`.
+#[repr(C)]
+pub struct LocalGuardShared<Act: LocalActor> {
+    // handle to the shared state
+    shared_state: Rc<LocalStateShared<Act>>,
+    // handle to the actor's dispatch task, to wake it up when it unblocks the task
+    dispatch_task: Task,
+    // cache a pointer, for better aliasing
+    ptr: *const Act,
+}
+
+/// Synchronization guard for exclusive (mutable) access to a local actor.
+///
+/// This type is notably `'static`, but not `Send`.
+#[repr(C)]
+pub struct LocalGuardMut<Act: LocalActor> {
+    // handle to the shared state
+    shared_state: Rc<LocalStateShared<Act>>,
+    // handle to the actor's dispatch task, to wake it up when it unblocks the task
+    dispatch_task: Task,
+    // cache a pointer, for better aliasing
+    ptr: *mut Act,
+}
+
+/// Handle to a local actor, which is the equivalent of `RootActor`.
+///
+/// A `LocalRootActor` is a future, and will resolve to the actor's end message.
+/// Since it is not `Send`, it must be run on a current-thread executor, such as
+/// with `reflex::local::run`.
+///
+/// This handle also contains and exposes the actor's mailbox.
+pub struct LocalRootActor<Act>
+where
+    Act: LocalActor,
+    Act::End: IntoResult,
+{
+    // set to None once the actor routine completes
+    actor: Option<LocalActorState<Act>>,
+    mailbox: LocalMailbox<Act::Message>,
+    end_recv: oneshot::Receiver<Act::End>,
+}
+
+impl<Act> LocalRootActor<Act>
+where
+    Act: LocalActor,
+    Act::End: IntoResult,
+    <Act::End as IntoResult>::Error: From<AbnormalClose>,
+{
+    /// Create a new local actor. It still must be run on an executor.
+    pub fn new(state: Act) -> Self {
+        let (
+            msg_send,
+            msg_recv,
+        ) = mpsc::unbounded();
+        let (
+            end_send,
+            end_recv,
+        ) = oneshot::channel();
+
+        let id = ActorId::next();
+        let path = ActorPath::root();
+        let shared = Rc::new(LocalStateShared {
+            id,
+            path: path.clone(),
+            user_state: UnsafeCell::new(Some(state)),
+            access_count: Cell::new(0),
+            release_mode: Cell::new(ReleaseMode::Normal),
+            end_send: Cell::new(Some(end_send)),
+        });
+        let actor = LocalActorState {
+            shared,
+            access_status: ActorAccessStatus::Available,
+            msg_recv,
+            curr_msg: None,
+            curr_batch: None,
+        };
+
+        LocalRootActor {
+            actor: Some(actor),
+            mailbox: LocalMailbox::new(msg_send, id, path),
+            end_recv,
+        }
+    }
+
+    pub fn mailbox(&self) -> &LocalMailbox<Act::Message> {
+        &self.mailbox
+    }
+}
+
+impl<Act> Future for LocalRootActor<Act>
+where
+    Act: LocalActor,
+    Act::End: IntoResult,
+    <Act::End as IntoResult>::Error: From<AbnormalClose>,
+{
+    type Item = <Act::End as IntoResult>::Item;
+    type Error = <Act::End as IntoResult>::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let done = match self.actor {
+            Some(ref mut actor) => !matches!(actor.poll(), Ok(Async::NotReady)),
+            None => false,
+        };
+        if done {
+            self.actor = None;
+        }
+        // the end value may arrive before the routine terminates, so that it still
+        // has to exit
+        if self.actor.is_some() {
+            return Ok(Async::NotReady);
+        }
+
+        match self.end_recv.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(end)) => end.into_result().map(Async::Ready),
+            Err(_) => Err(Self::Error::from(AbnormalClose)),
+        }
+    }
+}
+
+impl<Act: LocalActor> LocalStateShared<Act> {
+    /// Send the end value to the root handle, unless one was already sent.
+    fn send_end(&self, end: Act::End) {
+        if let Some(end_send) = self.end_send.take() {
+            if end_send.send(end).is_err() {
+                trace!("{}: root handle dropped before end value was sent", self.path);
+            }
+        }
+    }
+}
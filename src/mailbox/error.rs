@@ -0,0 +1,86 @@
+use crate::msg_union::{MessageTypeUnion, MailboxEntry};
+
+use std::fmt::{self, Debug, Formatter};
+
+#[cfg(feature = "failure-interop")]
+use failure::Fail;
+#[cfg(feature = "failure-interop")]
+use std::fmt::Display;
+
+/// Error from blocking the current thread to send into a mailbox, which returns
+/// the rejected message.
+pub enum SendBlockingError<T: MessageTypeUnion> {
+    /// The calling thread is running an executor task, which blocking could
+    /// deadlock, so the message was not sent.
+    InTask(MailboxEntry<T>),
+    /// The mailbox did not have capacity for the message before the timeout.
+    Timeout(MailboxEntry<T>),
+}
+
+impl<T: MessageTypeUnion> SendBlockingError<T> {
+    /// Extract the message which could not be sent.
+    pub fn into_inner(self) -> MailboxEntry<T> {
+        match self {
+            SendBlockingError::InTask(entry) => entry,
+            SendBlockingError::Timeout(entry) => entry,
+        }
+    }
+}
+
+impl<T: MessageTypeUnion> Debug for SendBlockingError<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            SendBlockingError::InTask(_) => f.write_str("InTask(..)"),
+            SendBlockingError::Timeout(_) => f.write_str("Timeout(..)"),
+        }
+    }
+}
+
+/// Error from a timed send into a mailbox, which returns the rejected message.
+///
+/// The mailbox did not have capacity for the message before the deadline.
+pub struct SendTimeoutError<T: MessageTypeUnion>(pub MailboxEntry<T>);
+
+impl<T: MessageTypeUnion> SendTimeoutError<T> {
+    /// Extract the message which could not be sent.
+    pub fn into_inner(self) -> MailboxEntry<T> {
+        self.0
+    }
+}
+
+impl<T: MessageTypeUnion> Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("SendTimeoutError(..)")
+    }
+}
+
+#[cfg(feature = "failure-interop")]
+impl<T> Fail for SendBlockingError<T>
+where
+    T: MessageTypeUnion,
+    MailboxEntry<T>: Sync,
+{}
+
+#[cfg(feature = "failure-interop")]
+impl<T: MessageTypeUnion> Display for SendBlockingError<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            SendBlockingError::InTask(_) => f.write_str("blocking send called from within an executor task"),
+            SendBlockingError::Timeout(_) => f.write_str("blocking send timed out"),
+        }
+    }
+}
+
+#[cfg(feature = "failure-interop")]
+impl<T> Fail for SendTimeoutError<T>
+where
+    T: MessageTypeUnion,
+    MailboxEntry<T>: Sync,
+{}
+
+#[cfg(feature = "failure-interop")]
+impl<T: MessageTypeUnion> Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("send timed out")
+    }
+}
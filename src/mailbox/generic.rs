@@ -1,9 +1,23 @@
 
 use super::ownership::Ownership;
-use crate::msg_union::{MessageTypeUnion, MailboxEntry};
+use super::error::{SendBlockingError, SendTimeoutError};
+use crate::Actor;
+use crate::manage::{ActorId, ActorPath, ActorMetrics};
+use crate::manage::dead_letter::{self, DeadLetterReason};
+use crate::internal::watch::ExitWatchers;
+use crate::msg_union::{MessageTypeUnion, MailboxEntry, Envelope, Inspection};
+use crate::spawn::blocking;
+use crate::util::park::block_until;
+use crate::util::timer::{self, Delay};
 
 use futures::prelude::*;
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
+use futures::{executor, future};
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::hash::{Hash, Hasher};
 
 /// A handle for sending messages to an actor.
 ///
@@ -17,18 +31,44 @@ use futures::sync::mpsc;
 /// - this type has methods which delegate to the `futures::sync::mpsc::Sender` methods:
 ///   - `try_send` (`Mailbox::send_now`)
 ///   - `is_closed`
+///
+/// Mailboxes compare equal and hash by the identity of their actor, regardless of
+/// ownership semantics, so they can be used as map keys.
 pub struct Mailbox<T: MessageTypeUnion, O: Ownership> {
-    pub(super) sender: mpsc::Sender<MailboxEntry<T>>,
+    pub(super) sender: mpsc::Sender<Envelope<T>>,
     pub(super) ownership: O,
+    pub(super) id: ActorId,
+    pub(super) path: ActorPath,
+    pub(crate) watchers: Arc<ExitWatchers>,
+    pub(crate) metrics: Arc<ActorMetrics>,
 }
 
 impl<T: MessageTypeUnion, O: Ownership> Mailbox<T, O> {
     /// Crate-internal constructor.
     pub (crate) fn new(
-        sender: mpsc::Sender<MailboxEntry<T>>,
+        sender: mpsc::Sender<Envelope<T>>,
         ownership: O,
+        id: ActorId,
+        path: ActorPath,
+        watchers: Arc<ExitWatchers>,
+        metrics: Arc<ActorMetrics>,
     ) -> Self {
-        Mailbox { sender, ownership, }
+        Mailbox { sender, ownership, id, path, watchers, metrics, }
+    }
+
+    /// The unique id of the actor.
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+
+    /// The hierarchical path of the actor.
+    pub fn path(&self) -> &ActorPath {
+        &self.path
+    }
+
+    /// The counters of the actor's message handling.
+    pub fn metrics(&self) -> &ActorMetrics {
+        &self.metrics
     }
 
     /// Send a message to the actor.
@@ -37,7 +77,84 @@ impl<T: MessageTypeUnion, O: Ownership> Mailbox<T, O> {
             Msg: Into<MailboxEntry<T>> {
 
         let msg = message.into();
-        mailbox_futures::MailboxSend::new(self,msg)
+        mailbox_futures::MailboxSend::new(self, Envelope::Entry(msg))
+    }
+
+    /// Send a message to the actor, which the actor discards instead of processing
+    /// if it is still queued at the expiry.
+    pub fn send_expiring<Msg>(self, message: Msg, expiry: Instant) -> mailbox_futures::MailboxSend<T, O>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        let msg = message.into();
+        mailbox_futures::MailboxSend::new(self, Envelope::Expiring(msg, expiry))
+    }
+
+    /// Send a message to the actor, which the actor discards instead of processing
+    /// if it is still queued once the time to live elapses.
+    pub fn send_with_ttl<Msg>(self, message: Msg, ttl: Duration) -> mailbox_futures::MailboxSend<T, O>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        self.send_expiring(message, timer::now() + ttl)
+    }
+
+    /// Send a message to the actor, unless the mailbox does not have capacity for
+    /// it before the timeout elapses.
+    ///
+    /// If it times out, the future fails with the rejected message.
+    pub fn send_timeout<Msg>(
+        self,
+        message: Msg,
+        timeout: Duration,
+    ) -> mailbox_futures::MailboxSendTimeout<T, O>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        self.send_until(message, timer::now() + timeout)
+    }
+
+    /// Send a message to the actor, unless the mailbox does not have capacity for
+    /// it before the deadline.
+    ///
+    /// If the deadline passes, the future fails with the rejected message.
+    pub fn send_until<Msg>(
+        self,
+        message: Msg,
+        deadline: Instant,
+    ) -> mailbox_futures::MailboxSendTimeout<T, O>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        let msg = message.into();
+        mailbox_futures::MailboxSendTimeout::new(
+            mailbox_futures::MailboxSend::new(self, Envelope::Entry(msg)),
+            Delay::new(deadline),
+        )
+    }
+
+    /// Read the actor's state, without defining a message type to do so.
+    ///
+    /// The closure is enqueued like a shared message, and runs under shared access
+    /// to the actor. The returned future resolves to the closure's return value.
+    ///
+    /// The future fails if the actor dies before running the closure, or if the
+    /// actor is not actually of type `Act`.
+    pub fn inspect<Act, F, R>(&self, f: F) -> mailbox_futures::MailboxInspect<T, O, R>
+        where
+            Act: Actor<Message = T>,
+            F: FnOnce(&Act) -> R + Send + 'static,
+            R: Send + 'static {
+
+        let (result_send, result_recv) = oneshot::channel();
+        let inspection: Inspection<Act> = Box::new(move |guard| {
+            let _ = result_send.send(f(&guard));
+        });
+        let send = mailbox_futures::MailboxSend::new(
+            self.clone(),
+            Envelope::Inspect(Box::new(inspection)),
+        );
+        mailbox_futures::MailboxInspect::new(send, result_recv)
     }
 
     /// Send a message to the actor, synchronously, unless there is back pressure.
@@ -50,14 +167,89 @@ impl<T: MessageTypeUnion, O: Ownership> Mailbox<T, O> {
         where
             Msg: Into<MailboxEntry<T>> {
 
-        let msg = message.into();
-        match self.sender.try_send(msg)
-            .err()
-            .filter(mpsc::TrySendError::is_full)
-            .map(mpsc::TrySendError::into_inner) {
+        self.try_send_envelope(Envelope::Entry(message.into()))
+    }
+
+    /// Send a message to the actor, synchronously, unless there is back pressure,
+    /// which the actor discards instead of processing if it is still queued at
+    /// the expiry.
+    ///
+    /// If the mailbox is full, the input message will be returned.
+    #[must_use = "send_now_expiring will return its input if unable to send now"]
+    pub fn send_now_expiring<Msg>(&mut self, message: Msg, expiry: Instant) -> Result<(), MailboxEntry<T>>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        self.try_send_envelope(Envelope::Expiring(message.into(), expiry))
+    }
 
-            None => Ok(()),
-            Some(rejected) => Err(rejected),
+    fn try_send_envelope(&mut self, envelope: Envelope<T>) -> Result<(), MailboxEntry<T>> {
+        match self.sender.try_send(envelope) {
+            Ok(()) => Ok(()),
+            Err(e) => if e.is_full() {
+                Err(e.into_inner().into_entry())
+            } else {
+                // swallow actor death, but post the message as a dead letter
+                dead_letter::post_envelope(self.id, &self.path, DeadLetterReason::ActorDead, e.into_inner());
+                Ok(())
+            },
+        }
+    }
+
+    /// Send a message to the actor, blocking the current thread until the mailbox
+    /// has capacity for it.
+    ///
+    /// This is for threads outside of any executor, and for actors spawned onto
+    /// blocking spawners, such as with `spawn_blocking`. If called from within an
+    /// executor task, which blocking could deadlock, this fails without sending.
+    /// Only `futures` 0.1 tasks, and with the `async-std` feature, async-std tasks,
+    /// are detected, so this must not be called from the tasks of other executors.
+    ///
+    /// As usual, if the actor is dead, this will swallow that error.
+    pub fn send_blocking<Msg>(&mut self, message: Msg) -> Result<(), SendBlockingError<T>>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        self.send_blocking_until(message.into(), None)
+    }
+
+    /// Send a message to the actor, blocking the current thread until the mailbox
+    /// has capacity for it, or until the timeout elapses.
+    ///
+    /// Like `send_blocking`, this fails without sending if called from within an
+    /// executor task, other than on the threads of blocking spawners.
+    pub fn send_blocking_timeout<Msg>(
+        &mut self,
+        message: Msg,
+        timeout: Duration,
+    ) -> Result<(), SendBlockingError<T>>
+        where
+            Msg: Into<MailboxEntry<T>> {
+
+        self.send_blocking_until(message.into(), Some(Instant::now() + timeout))
+    }
+
+    fn send_blocking_until(
+        &mut self,
+        message: MailboxEntry<T>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendBlockingError<T>> {
+        if !blocking::may_block() {
+            return Err(SendBlockingError::InTask(message));
+        }
+
+        // wait until this sender may send, rather than sending through a clone,
+        // since every sender is guaranteed a slot of its own
+        let ready = {
+            let sender = &mut self.sender;
+            let mut ready = executor::spawn(future::poll_fn(|| sender.poll_ready()));
+            block_until(&mut ready, deadline)
+        };
+        match ready {
+            // if the actor is dead, this posts the message as a dead letter
+            Some(_) => self.try_send_envelope(Envelope::Entry(message))
+                .map_err(SendBlockingError::Timeout),
+            None => Err(SendBlockingError::Timeout(message)),
         }
     }
 
@@ -79,10 +271,42 @@ impl<T: MessageTypeUnion, O: Ownership> Clone for Mailbox<T, O> {
         Mailbox {
             sender: self.sender.clone(),
             ownership: self.ownership.clone(),
+            id: self.id,
+            path: self.path.clone(),
+            watchers: self.watchers.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 }
 
+impl<T, O1, O2> PartialEq<Mailbox<T, O2>> for Mailbox<T, O1>
+where
+    T: MessageTypeUnion,
+    O1: Ownership,
+    O2: Ownership,
+{
+    fn eq(&self, other: &Mailbox<T, O2>) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: MessageTypeUnion, O: Ownership> Eq for Mailbox<T, O> {}
+
+impl<T: MessageTypeUnion, O: Ownership> Hash for Mailbox<T, O> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T: MessageTypeUnion, O: Ownership> fmt::Debug for Mailbox<T, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mailbox")
+            .field("id", &self.id)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
 /// Future types and code for mailboxes.
 ///
 /// Largely boilerplate.
@@ -95,8 +319,14 @@ pub mod mailbox_futures {
         type SinkError = ();
 
         fn start_send(&mut self, msg: Self::SinkItem) -> StartSend<Self::SinkItem, ()> {
-            self.sender.start_send(msg)
-                .map_err(|_| trace!("mailbox Sink::start_send failure"))
+            match self.sender.start_send(Envelope::Entry(msg)) {
+                Ok(start) => Ok(start.map(Envelope::into_entry)),
+                Err(e) => {
+                    trace!("mailbox Sink::start_send failure");
+                    dead_letter::post_envelope(self.id, &self.path, DeadLetterReason::ActorDead, e.into_inner());
+                    Err(())
+                },
+            }
         }
 
         fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
@@ -116,7 +346,7 @@ pub mod mailbox_futures {
             T: MessageTypeUnion,
             O: Ownership {
         mailbox: Option<Mailbox<T, O>>,
-        message: Option<MailboxEntry<T>>,
+        message: Option<Envelope<T>>,
     }
 
     impl<T, O> MailboxSend<T, O>
@@ -126,13 +356,23 @@ pub mod mailbox_futures {
         /// Private constructor.
         pub (super) fn new(
             mailbox: Mailbox<T, O>,
-            message: MailboxEntry<T>,
+            message: Envelope<T>,
         ) -> Self {
             MailboxSend {
                 mailbox: Some(mailbox),
                 message: Some(message),
             }
         }
+
+        /// Take back the message, unless the channel has already accepted it.
+        pub (super) fn take_message(&mut self) -> Option<Envelope<T>> {
+            self.message.take()
+        }
+
+        /// Take the mailbox, once the send has been given up.
+        pub (super) fn take_mailbox(&mut self) -> Option<Mailbox<T, O>> {
+            self.mailbox.take()
+        }
     }
 
     impl<T, O> Future for MailboxSend<T, O>
@@ -173,8 +413,16 @@ pub mod mailbox_futures {
 
                         return Ok(Async::NotReady);
                     },
-                    // swallow error/actor-death
-                    Err(_) => return Ok(Async::Ready(mailbox)),
+                    // swallow error/actor-death, but post the message as a dead letter
+                    Err(e) => {
+                        dead_letter::post_envelope(
+                            mailbox.id,
+                            &mailbox.path,
+                            DeadLetterReason::ActorDead,
+                            e.into_inner(),
+                        );
+                        return Ok(Async::Ready(mailbox));
+                    },
                 }
             }
 
@@ -192,4 +440,107 @@ pub mod mailbox_futures {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Future for sending into a mailbox, which gives up at a deadline.
+    pub struct MailboxSendTimeout<T, O>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+        send: MailboxSend<T, O>,
+        delay: Delay,
+    }
+
+    impl<T, O> MailboxSendTimeout<T, O>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+        /// Private constructor.
+        pub (super) fn new(send: MailboxSend<T, O>, delay: Delay) -> Self {
+            MailboxSendTimeout { send, delay }
+        }
+    }
+
+    impl<T, O> Future for MailboxSendTimeout<T, O>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+
+        type Item = Mailbox<T, O>;
+        type Error = SendTimeoutError<T>;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            // MailboxSend swallows errors, so only its readiness matters
+            if let Ok(Async::Ready(mailbox)) = self.send.poll() {
+                return Ok(Async::Ready(mailbox));
+            }
+            match self.delay.poll() {
+                Ok(Async::Ready(())) => (),
+                _ => return Ok(Async::NotReady),
+            };
+
+            // if the message was already accepted, it is only left to be flushed,
+            // so the send has succeeded
+            match self.send.take_message() {
+                Some(message) => Err(SendTimeoutError(message.into_entry())),
+                None => match self.send.take_mailbox() {
+                    Some(mailbox) => Ok(Async::Ready(mailbox)),
+                    None => {
+                        warn!("MailboxSendTimeout polled after completion");
+                        Ok(Async::NotReady)
+                    },
+                },
+            }
+        }
+    }
+
+    /// Future for inspecting an actor's state through a mailbox.
+    pub struct MailboxInspect<T, O, R>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+        // becomes None once the inspection has been sent
+        send: Option<MailboxSend<T, O>>,
+        result_recv: oneshot::Receiver<R>,
+    }
+
+    impl<T, O, R> MailboxInspect<T, O, R>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+        /// Private constructor.
+        pub (super) fn new(
+            send: MailboxSend<T, O>,
+            result_recv: oneshot::Receiver<R>,
+        ) -> Self {
+            MailboxInspect {
+                send: Some(send),
+                result_recv,
+            }
+        }
+    }
+
+    impl<T, O, R> Future for MailboxInspect<T, O, R>
+        where
+            T: MessageTypeUnion,
+            O: Ownership {
+
+        type Item = R;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            // first, finish sending the inspection into the mailbox
+            if let Some(ref mut send) = self.send {
+                match send.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) | Err(()) => (),
+                }
+            }
+            self.send = None;
+
+            // then, wait for the actor to run it
+            // the result sender is dropped if the actor dies first
+            self.result_recv.poll()
+                .map_err(|_| trace!("actor died before running inspection"))
+        }
+    }
+}
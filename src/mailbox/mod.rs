@@ -8,6 +8,11 @@ use crate::msg_union::MessageTypeUnion;
 /// Handling of actor ownership semantics.
 pub mod ownership;
 
+/// Errors of sending into mailboxes.
+pub mod error;
+
+pub use self::error::{SendBlockingError, SendTimeoutError};
+
 /// Code that is generic over actor-ownership semantics.
 ///
 /// More usable type aliases exist in this parent module.
@@ -47,6 +52,10 @@ impl<Msg: MessageTypeUnion> MailboxOwned<Msg> {
         Mailbox {
             sender: self.sender,
             ownership: Weak,
+            id: self.id,
+            path: self.path,
+            watchers: self.watchers,
+            metrics: self.metrics,
         }
     }
 }
\ No newline at end of file
@@ -1,11 +1,8 @@
 
-use super::MailboxOwned;
+use super::{MailboxOwned, MailboxWeak};
 use super::generic::Mailbox;
 use crate::util::drop_signal::DropSignalArc;
-use crate::msg_union::{MessageTypeUnion, MailboxEntry};
-
-
-use futures::sync::mpsc;
+use crate::msg_union::MessageTypeUnion;
 
 /// Mechanism for mailbox actor-ownership semantics.
 pub trait Ownership: Clone + Send + Sync + 'static {}
@@ -16,13 +13,14 @@ pub trait Ownership: Clone + Send + Sync + 'static {}
 /// is dropped.
 #[derive(Clone)]
 pub struct Supervisor {
-    drop_signal: DropSignalArc
+    // only held for its drop behavior
+    _drop_signal: DropSignalArc
 }
 
 impl Supervisor {
     /// Crate-internal constructor.
     pub (crate) fn new(drop_signal: DropSignalArc) -> Self {
-        Supervisor { drop_signal }
+        Supervisor { _drop_signal: drop_signal }
     }
 }
 
@@ -41,10 +39,19 @@ impl Ownership for Weak {}
 
 impl<T: MessageTypeUnion> MailboxOwned<T> {
     /// Crate-internal constructor.
+    ///
+    /// Upgrades a weak mailbox, given the actor's drop signal.
     pub (crate) fn new_owned(
-        msg_send: mpsc::Sender<MailboxEntry<T>>,
+        mailbox: MailboxWeak<T>,
         drop_signal: DropSignalArc,
     ) -> Self {
-        Mailbox::new(msg_send, Supervisor::new(drop_signal))
+        Mailbox {
+            sender: mailbox.sender,
+            ownership: Supervisor::new(drop_signal),
+            id: mailbox.id,
+            path: mailbox.path,
+            watchers: mailbox.watchers,
+            metrics: mailbox.metrics,
+        }
     }
 }
\ No newline at end of file
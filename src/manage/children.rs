@@ -0,0 +1,52 @@
+use super::identity::{ActorId, ActorPath};
+use crate::{
+    mailbox::MailboxWeak,
+    msg_union::MessageTypeUnion,
+};
+
+use std::{
+    any::Any,
+    sync::Arc,
+};
+
+/// A supervisor's information about one of its live subordinates.
+#[derive(Clone)]
+pub struct ChildInfo {
+    id: ActorId,
+    path: ActorPath,
+    // a boxed MailboxWeak of the child's message type union
+    mailbox: Arc<dyn Any + Send + Sync>,
+}
+
+impl ChildInfo {
+    /// Crate-internal constructor.
+    pub(crate) fn new<T: MessageTypeUnion>(mailbox: MailboxWeak<T>) -> Self {
+        ChildInfo {
+            id: mailbox.id(),
+            path: mailbox.path().clone(),
+            mailbox: Arc::new(mailbox),
+        }
+    }
+
+    /// The subordinate's unique id.
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+
+    /// The name which the subordinate was created with.
+    ///
+    /// Unless explicitly named, this is its id.
+    pub fn name(&self) -> &str {
+        self.path.name()
+    }
+
+    /// The subordinate's hierarchical path.
+    pub fn path(&self) -> &ActorPath {
+        &self.path
+    }
+
+    /// A weak mailbox to the subordinate, if its message type union is `T`.
+    pub fn mailbox<T: MessageTypeUnion>(&self) -> Option<MailboxWeak<T>> {
+        self.mailbox.downcast_ref::<MailboxWeak<T>>().cloned()
+    }
+}
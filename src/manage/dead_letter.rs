@@ -0,0 +1,172 @@
+use super::identity::{ActorId, ActorPath};
+use crate::{
+    msg_union::{MessageTypeUnion, MailboxEntry, Envelope},
+    util::lock,
+};
+
+use std::{
+    any::{Any, type_name},
+    fmt::{self, Debug, Formatter},
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use futures::{
+    Stream,
+    Poll,
+    sync::mpsc,
+};
+
+/// A message which was not delivered to the actor it was sent to.
+#[derive(Clone, Debug)]
+pub struct DeadLetter {
+    /// The type name of the message, which is the shared or mutable message union
+    /// of the actor, depending on how the message was sent.
+    pub message_type: &'static str,
+    /// The id of the actor which the message was sent to.
+    pub actor: ActorId,
+    /// The path of the actor which the message was sent to.
+    pub path: ActorPath,
+    /// Why the message was not delivered.
+    pub reason: DeadLetterReason,
+    /// The message itself, which one subscriber may take.
+    pub message: DeadMessage,
+}
+
+/// Why a message was not delivered.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DeadLetterReason {
+    /// The actor was already dead when the message was sent.
+    ActorDead,
+    /// The message expired before the actor could process it.
+    Expired,
+    /// The actor terminated while the message was still queued.
+    Terminated,
+}
+
+/// The boxed message of a dead letter, which is shared by the clones of the
+/// dead letter that each subscriber receives.
+#[derive(Clone)]
+pub struct DeadMessage(Arc<Mutex<Option<Box<dyn Any + Send>>>>);
+
+impl DeadMessage {
+    /// Take the boxed message, unless another subscriber already took it.
+    pub fn take(&self) -> Option<Box<dyn Any + Send>> {
+        lock(&self.0).take()
+    }
+}
+
+impl Debug for DeadMessage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("DeadMessage(..)")
+    }
+}
+
+/// Subscription to the dead letters of every actor in the process.
+///
+/// This is a `Stream` of dead letters, which unsubscribes when dropped. Dead
+/// letters are only collected while there are subscribers.
+pub struct DeadLetters {
+    id: u64,
+    letter_recv: mpsc::UnboundedReceiver<DeadLetter>,
+}
+
+/// Subscribe to the dead letters of every actor in the process.
+pub fn subscribe() -> DeadLetters {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (letter_send, letter_recv) = mpsc::unbounded();
+    let mut subscribers = lock(&OFFICE.subscribers);
+    subscribers.push((id, letter_send));
+    OFFICE.count.store(subscribers.len(), Ordering::Relaxed);
+    DeadLetters { id, letter_recv }
+}
+
+impl Stream for DeadLetters {
+    type Item = DeadLetter;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<DeadLetter>, ()> {
+        self.letter_recv.poll()
+    }
+}
+
+impl Drop for DeadLetters {
+    fn drop(&mut self) {
+        let mut subscribers = lock(&OFFICE.subscribers);
+        subscribers.retain(|&(id, _)| id != self.id);
+        OFFICE.count.store(subscribers.len(), Ordering::Relaxed);
+    }
+}
+
+struct Office {
+    subscribers: Mutex<Vec<(u64, mpsc::UnboundedSender<DeadLetter>)>>,
+    // the number of subscribers, to skip collecting dead letters without locking
+    count: AtomicUsize,
+}
+
+static OFFICE: Office = Office {
+    subscribers: Mutex::new(Vec::new()),
+    count: AtomicUsize::new(0),
+};
+
+/// Post the messages of an undelivered mailbox entry as dead letters.
+pub(crate) fn post<T: MessageTypeUnion>(
+    actor: ActorId,
+    path: &ActorPath,
+    reason: DeadLetterReason,
+    entry: MailboxEntry<T>,
+) {
+    trace!("{}: message not delivered, {:?}", path, reason);
+    if OFFICE.count.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    // the concrete type depends on the kind of the entry
+    match entry {
+        MailboxEntry::Shared(msg_vec) => for msg in msg_vec {
+            post_message(actor, path, reason, type_name::<T::SharedUnion>(), Box::new(msg));
+        },
+        MailboxEntry::Mut(msg) => {
+            post_message(actor, path, reason, type_name::<T::MutUnion>(), Box::new(msg));
+        },
+    };
+}
+
+/// Post the messages of an undelivered envelope as dead letters.
+///
+/// Inspections are not messages, so they are not posted.
+pub(crate) fn post_envelope<T: MessageTypeUnion>(
+    actor: ActorId,
+    path: &ActorPath,
+    reason: DeadLetterReason,
+    envelope: Envelope<T>,
+) {
+    match envelope {
+        Envelope::Entry(entry) | Envelope::Expiring(entry, _) => post(actor, path, reason, entry),
+        Envelope::Inspect(_) => trace!("{}: inspection not delivered, {:?}", path, reason),
+    };
+}
+
+fn post_message(
+    actor: ActorId,
+    path: &ActorPath,
+    reason: DeadLetterReason,
+    message_type: &'static str,
+    msg: Box<dyn Any + Send>,
+) {
+    let letter = DeadLetter {
+        message_type,
+        actor,
+        path: path.clone(),
+        reason,
+        message: DeadMessage(Arc::new(Mutex::new(Some(msg)))),
+    };
+    let mut subscribers = lock(&OFFICE.subscribers);
+    subscribers.retain(|(_, letter_send)| letter_send.unbounded_send(letter.clone()).is_ok());
+    OFFICE.count.store(subscribers.len(), Ordering::Relaxed);
+}
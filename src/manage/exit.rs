@@ -0,0 +1,19 @@
+/// The way in which an actor terminated, as reported to actors watching it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ExitReason {
+    /// The actor produced an end value, by being deleted or stopped.
+    Ended,
+    /// The actor terminated without producing an end value, because it was
+    /// orphaned or killed.
+    Killed,
+    /// The actor routine was dropped before it terminated, such as due to a panic
+    /// in one of the actor's message handlers, or its executor shutting down.
+    Dropped,
+}
+
+impl ExitReason {
+    /// Whether the actor terminated without producing an end value.
+    pub fn is_abnormal(self) -> bool {
+        self != ExitReason::Ended
+    }
+}
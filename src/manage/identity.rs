@@ -0,0 +1,69 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Unique identifier of an actor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ActorId(u64);
+
+impl ActorId {
+    /// Allocate a new, process-wide unique actor id.
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        ActorId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The id as an integer.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Display for ActorId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Hierarchical path of an actor, formed from the names of its supervisor chain,
+/// such as `/root/server/client-42`.
+///
+/// Paths are for human consumption. Unlike ids, they are not necessarily unique,
+/// since sibling actors may be given the same name.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ActorPath(Arc<str>);
+
+impl ActorPath {
+    /// The path of a root actor.
+    pub(crate) fn root() -> Self {
+        ActorPath("/root".into())
+    }
+
+    /// The path of a subordinate with the given name.
+    pub(crate) fn child(&self, name: &str) -> Self {
+        ActorPath(format!("{}/{}", self.0, name).into())
+    }
+
+    /// The path as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The last segment of the path, which is the actor's own name.
+    pub fn name(&self) -> &str {
+        match self.0.rfind('/') {
+            Some(i) => &self.0[i + 1..],
+            None => &self.0,
+        }
+    }
+}
+
+impl Display for ActorPath {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of an actor's message handling, shared by its mailboxes.
+#[derive(Debug, Default)]
+pub struct ActorMetrics {
+    expired: AtomicU64,
+}
+
+impl ActorMetrics {
+    /// The number of messages which the actor discarded, because they expired
+    /// before it could process them.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Count messages which were discarded upon expiring.
+    pub(crate) fn record_expired(&self, count: u64) {
+        self.expired.fetch_add(count, Ordering::Relaxed);
+    }
+}
@@ -1,26 +1,59 @@
 
 use crate::{
     Actor,
+    spawn::Spawner,
     mailbox::{
         MailboxOwned,
         MailboxWeak,
     },
+    util::drop_signal::DropSignalArcWeak,
     internal::{
         ActorState,
-        queue::MsgQueue,
         create::{
+            ActorInbox,
             create_actor,
             create_actor_using_mailbox,
         },
+        supervise::{SupervisorLink, EndSignalRecv},
     },
 };
 
 use futures::{
     {Future, Stream, Poll, Async},
-    sync::mpsc,
-    future::Fuse,
+    sync::{mpsc, oneshot},
 };
 
+use std::sync::Arc;
+
+/// Supervisors' knowledge of their subordinates.
+pub mod children;
+
+/// Identification of actors.
+pub mod identity;
+
+/// Lookup of actors by name.
+pub mod registry;
+
+/// Reporting of actor termination.
+pub mod exit;
+
+/// Ownership of actor trees by an executor.
+pub mod system;
+
+/// Counters of actors' message handling.
+pub mod metrics;
+
+/// Reporting of messages which were not delivered.
+pub mod dead_letter;
+
+pub use self::children::ChildInfo;
+pub use self::identity::{ActorId, ActorPath};
+pub use self::registry::{Registry, NameTaken};
+pub use self::exit::ExitReason;
+pub use self::system::{ActorSystem, RootEnd, ShutdownTimeout};
+pub use self::metrics::ActorMetrics;
+pub use self::dead_letter::{DeadLetter, DeadLetterReason, DeadLetters};
+
 #[cfg(feature = "failure-interop")]
 use failure::Fail;
 #[cfg(feature = "failure-interop")]
@@ -37,9 +70,11 @@ where
     Act: Actor,
     Act::End: IntoResult,
 {
-    actor: Fuse<ActorState<Act>>,
+    // set to None once the actor routine completes
+    actor: Option<ActorState<Act>>,
     mailbox: MailboxOwned<Act::Message>,
-    end_signal_recv: mpsc::UnboundedReceiver<Act::End>,
+    end_signal_recv: EndSignalRecv<Act::End>,
+    registry: Registry,
 }
 
 impl<Act> RootActor<Act>
@@ -50,26 +85,65 @@ where
 {
     /// Create a new root actor. It still must be spawned onto an executor.
     pub fn new(state: Act) -> Self {
+        Self::create(state, false, None)
+    }
+
+    /// Create a new root actor in structured mode. It still must be spawned onto
+    /// an executor.
+    ///
+    /// In structured mode, when an actor is deleted or orphaned, it first stops all
+    /// of its subordinates, and waits for them to terminate, before it produces its
+    /// end value. Subordinates are stopped in the same way, so the whole tree below
+    /// the actor is stopped depth-first. Subordinates of a structured actor are
+    /// structured as well.
+    pub fn new_structured(state: Act) -> Self {
+        Self::create(state, true, None)
+    }
+
+    /// Create a root actor, which belongs to the given actor system, if any.
+    pub(crate) fn create(state: Act, structured: bool, system: Option<ActorSystem>) -> Self {
         let (
             end_signal_send,
             end_signal_recv,
         ) = mpsc::unbounded();
 
-        let (
-            actor,
-            msg_sender,
-            drop_signal_send,
-        ) = create_actor(state, end_signal_send);
+        let registry = match system {
+            Some(ref system) => system.registry().clone(),
+            None => Registry::new(),
+        };
+        let link = SupervisorLink::root(
+            end_signal_send,
+            structured,
+            registry.clone(),
+            system,
+        );
+        // a root actor has no supervisor to stop it, so its stop sender is unused
+        let created = create_actor(state, link);
 
-        let mailbox = MailboxOwned::new_owned(msg_sender, drop_signal_send.arc());
+        let mailbox = MailboxOwned::new_owned(
+            created.mailbox,
+            created.kill_signal_send,
+        );
 
         RootActor {
-            actor: actor.fuse(),
+            actor: Some(created.state),
             mailbox,
             end_signal_recv,
+            registry,
         }
     }
 
+    /// Process the messages of shared batches concurrently, by spawning each
+    /// one as a task onto the given spawner.
+    ///
+    /// The actor remains in shared access until every one of those tasks completes.
+    pub fn parallel_shared<S: Spawner>(mut self, spawner: S) -> Self {
+        if let Some(ref mut actor) = self.actor {
+            actor.set_shared_spawner(Arc::new(spawner));
+        }
+        self
+    }
+
     pub fn mailbox(&self) -> &MailboxOwned<Act::Message> {
         &self.mailbox
     }
@@ -82,6 +156,11 @@ where
     pub fn mailbox_handle(&self) -> MailboxWeak<Act::Message> {
         self.mailbox.clone().downgrade()
     }
+
+    /// The registry shared by this actor tree.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
 }
 
 impl<Act> Future for RootActor<Act>
@@ -94,19 +173,24 @@ where
     type Error = <Act::End as IntoResult>::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let _ = self.actor.poll();
+        let done = match self.actor {
+            Some(ref mut actor) => !matches!(actor.poll(), Ok(Async::NotReady)),
+            None => false,
+        };
+        if done {
+            self.actor = None;
+        }
+        // the end value may arrive before the routine terminates, such as when the
+        // routine yields between messages, so that it still has to exit
+        if self.actor.is_some() {
+            return Ok(Async::NotReady);
+        }
 
         match self.end_signal_recv.poll() {
             Ok(Async::NotReady) => Ok(Async::NotReady),
-            Ok(Async::Ready(Some(end))) => (
-                end.into_result().map(Async::Ready)
-            ),
-            Ok(Async::Ready(_)) => (
-                Err(Self::Error::from(AbnormalClose))
-            ),
-            Err(_) => (
-                Err(Self::Error::from(AbnormalClose))
-            ),
+            Ok(Async::Ready(Some((_, end)))) => end.into_result().map(Async::Ready),
+            Ok(Async::Ready(_)) => Err(Self::Error::from(AbnormalClose)),
+            Err(_) => Err(Self::Error::from(AbnormalClose)),
         }
     }
 }
@@ -126,6 +210,15 @@ impl<Act: Actor> SubordinateActor<Act> {
     pub (crate) fn new(actor: ActorState<Act>) -> Self {
         SubordinateActor { actor }
     }
+
+    /// Process the messages of shared batches concurrently, by spawning each
+    /// one as a task onto the given spawner.
+    ///
+    /// The actor remains in shared access until every one of those tasks completes.
+    pub fn parallel_shared<S: Spawner>(mut self, spawner: S) -> Self {
+        self.actor.set_shared_spawner(Arc::new(spawner));
+        self
+    }
 }
 
 impl<Act: Actor> Future for SubordinateActor<Act> {
@@ -137,28 +230,57 @@ impl<Act: Actor> Future for SubordinateActor<Act> {
     }
 }
 
+/// A supervisor's handle to one of its subordinates, with which it can terminate
+/// the subordinate.
+///
+/// This is returned from using an actor guard to spawn a subordinate. Unlike
+/// dropping the subordinate's `MailboxOwned`, this terminates the subordinate
+/// regardless of how many other clones of that mailbox exist.
+pub struct SubordinateHandle<Act: Actor> {
+    kill_signal: DropSignalArcWeak,
+    stop_send: oneshot::Sender<Act::End>,
+}
+
+impl<Act: Actor> SubordinateHandle<Act> {
+    /// Crate-internal constructor.
+    pub (crate) fn new(
+        kill_signal: DropSignalArcWeak,
+        stop_send: oneshot::Sender<Act::End>,
+    ) -> Self {
+        SubordinateHandle { kill_signal, stop_send }
+    }
+
+    /// Terminate the subordinate, without an end value.
+    ///
+    /// Like an orphaned actor, it stops processing messages, and the supervisor does
+    /// not receive a `SubordinateEnd` message from it.
+    pub fn kill(self) {
+        self.kill_signal.send();
+    }
+
+    /// Terminate the subordinate, as if it had produced the given end value.
+    ///
+    /// The supervisor will receive the end value as a `SubordinateEnd` message,
+    /// unless the subordinate already produced an end value of its own.
+    pub fn stop(self, reason: Act::End) {
+        let _ = self.stop_send.send(reason);
+        self.kill_signal.send();
+    }
+}
+
 /// The receiving end of an already-created mailbox, ready for an actor
 /// to be plugged in as subordinate. This pattern allows a user to create
 /// circular chains of ActorMailboxWeak.
 #[must_use = "ActorSocket must be completed then polled"]
 pub struct ActorSocket<Act: Actor> {
-    msg_recv: MsgQueue<Act>,
-    end_signal_send: mpsc::UnboundedSender<Act::End>,
-    subord_end_signal_send: mpsc::UnboundedSender<Act::SubordinateEnd>,
+    inbox: ActorInbox<Act>,
+    link: SupervisorLink<Act>,
 }
 
 impl<Act: Actor> ActorSocket<Act> {
     /// Crate-internal constructor.
-    pub(crate) fn new(
-        msg_recv: MsgQueue<Act>,
-        end_signal_send: mpsc::UnboundedSender<Act::End>,
-        subord_end_signal_send: mpsc::UnboundedSender<Act::SubordinateEnd>,
-    ) -> Self {
-        ActorSocket {
-            msg_recv,
-            end_signal_send,
-            subord_end_signal_send,
-        }
+    pub(crate) fn new(inbox: ActorInbox<Act>, link: SupervisorLink<Act>) -> Self {
+        ActorSocket { inbox, link }
     }
 
     /// Plug in the actor state to produce to actor future, which must
@@ -166,9 +288,8 @@ impl<Act: Actor> ActorSocket<Act> {
     pub fn complete(self, state: Act) -> SubordinateActor<Act> {
         let actor = create_actor_using_mailbox(
             state,
-            self.msg_recv,
-            self.end_signal_send,
-            self.subord_end_signal_send,
+            self.inbox,
+            self.link,
         );
         SubordinateActor::new(actor)
     }
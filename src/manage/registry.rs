@@ -0,0 +1,110 @@
+use super::identity::ActorId;
+use crate::{
+    Actor,
+    mailbox::MailboxWeak,
+    util::lock,
+};
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "failure-interop")]
+use failure::Fail;
+#[cfg(feature = "failure-interop")]
+use std::fmt::{self, Display, Formatter};
+
+/// Directory of actors registered under names, shared by an actor tree.
+///
+/// Every actor of a tree, from its root down, sees the same registry. Actors
+/// register themselves through their guards, and are removed from the registry
+/// once their routine terminates.
+///
+/// This handle is `Clone`, and clones refer to the same registry.
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<Mutex<HashMap<String, RegistryEntry>>>,
+}
+
+struct RegistryEntry {
+    id: ActorId,
+    // the type of the registered actor, so that names are typed
+    actor_type: TypeId,
+    // a boxed MailboxWeak of the actor's message type union
+    mailbox: Box<dyn Any + Send + Sync>,
+}
+
+impl Registry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up the mailbox of the actor registered under the given name.
+    ///
+    /// Returns `None` if no actor is registered under that name, or if the
+    /// registered actor is not of type `Act`.
+    pub fn lookup<Act: Actor>(&self, name: &str) -> Option<MailboxWeak<Act::Message>> {
+        let entries = lock(&self.entries);
+        entries.get(name)
+            .filter(|entry| entry.actor_type == TypeId::of::<Act>())
+            .and_then(|entry| entry.mailbox.downcast_ref::<MailboxWeak<Act::Message>>())
+            .cloned()
+    }
+
+    /// Whether any actor is registered under the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        lock(&self.entries).contains_key(name)
+    }
+
+    /// Register an actor's mailbox under a name, unless the name is taken.
+    pub(crate) fn register<Act: Actor>(
+        &self,
+        name: String,
+        mailbox: MailboxWeak<Act::Message>,
+    ) -> Result<(), NameTaken> {
+        let mut entries = lock(&self.entries);
+        if entries.contains_key(&name) {
+            return Err(NameTaken);
+        }
+        entries.insert(name, RegistryEntry {
+            id: mailbox.id(),
+            actor_type: TypeId::of::<Act>(),
+            mailbox: Box::new(mailbox),
+        });
+        Ok(())
+    }
+
+    /// Remove a name, if it is registered to the given actor.
+    pub(crate) fn unregister(&self, name: &str, id: ActorId) -> bool {
+        let mut entries = lock(&self.entries);
+        match entries.get(name) {
+            Some(entry) if entry.id == id => {
+                entries.remove(name);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Remove every name registered to the given actor.
+    pub(crate) fn unregister_all(&self, id: ActorId) {
+        lock(&self.entries).retain(|_, entry| entry.id != id);
+    }
+}
+
+/// Error which denotes that a name is already registered to another actor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct NameTaken;
+
+#[cfg(feature = "failure-interop")]
+impl Fail for NameTaken {}
+
+#[cfg(feature = "failure-interop")]
+impl Display for NameTaken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("name already registered")
+    }
+}
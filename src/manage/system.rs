@@ -0,0 +1,290 @@
+use super::{
+    RootActor,
+    AbnormalClose,
+    IntoResult,
+    identity::{ActorId, ActorPath},
+    registry::Registry,
+};
+use crate::{
+    Actor,
+    mailbox::MailboxOwned,
+    internal::SharedSpawner,
+    spawn::{Spawner, BoxedTask, blocking::DedicatedThreads},
+    util::{
+        lock,
+        drop_signal::DropSignalArcWeak,
+    },
+};
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, Condvar},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    Future,
+    Poll,
+    Async,
+    sync::oneshot,
+};
+
+#[cfg(feature = "failure-interop")]
+use failure::Fail;
+#[cfg(feature = "failure-interop")]
+use std::fmt::{self, Display, Formatter};
+
+/// Owner of actor trees, which spawns their actors onto a spawner.
+///
+/// Actors of the system spawn subordinates with `spawn` on their guards, rather
+/// than spawning the futures returned from `manage` themselves. The system keeps
+/// track of every live actor, so that it can stop all of them on `shutdown`.
+///
+/// All actors of the system share one registry.
+///
+/// This handle is `Clone`, and clones refer to the same system.
+#[derive(Clone)]
+pub struct ActorSystem {
+    inner: Arc<SystemInner>,
+}
+
+struct SystemInner {
+    spawner: SharedSpawner,
+    registry: Registry,
+    live: Mutex<LiveActors>,
+    // notified whenever an actor terminates
+    terminated: Condvar,
+}
+
+struct LiveActors {
+    actors: BTreeMap<ActorId, LiveActor>,
+    shutting_down: bool,
+}
+
+struct LiveActor {
+    path: ActorPath,
+    kill_signal: DropSignalArcWeak,
+}
+
+impl ActorSystem {
+    /// Create an actor system which spawns its actors onto the given spawner.
+    pub fn new<S: Spawner>(spawner: S) -> Self {
+        ActorSystem {
+            inner: Arc::new(SystemInner {
+                spawner: Arc::new(spawner),
+                registry: Registry::new(),
+                live: Mutex::new(LiveActors {
+                    actors: BTreeMap::new(),
+                    shutting_down: false,
+                }),
+                terminated: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Spawn a root actor.
+    ///
+    /// Returns the root's mailbox, and a future which resolves to its end value.
+    pub fn spawn_root<Act>(&self, state: Act) -> (MailboxOwned<Act::Message>, RootEnd<Act>)
+    where
+        Act: Actor,
+        Act::End: IntoResult,
+        <Act::End as IntoResult>::Item: Send + 'static,
+        <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    {
+        self.spawn_root_actor(RootActor::create(state, false, Some(self.clone())))
+    }
+
+    /// Spawn a root actor in structured mode.
+    ///
+    /// Returns the root's mailbox, and a future which resolves to its end value.
+    pub fn spawn_root_structured<Act>(
+        &self,
+        state: Act,
+    ) -> (MailboxOwned<Act::Message>, RootEnd<Act>)
+    where
+        Act: Actor,
+        Act::End: IntoResult,
+        <Act::End as IntoResult>::Item: Send + 'static,
+        <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    {
+        self.spawn_root_actor(RootActor::create(state, true, Some(self.clone())))
+    }
+
+    /// Spawn a root actor onto a dedicated thread, rather than onto the system's
+    /// spawner, for an actor which blocks in its message handlers.
+    ///
+    /// Returns the root's mailbox, and a future which resolves to its end value.
+    pub fn spawn_root_blocking<Act>(&self, state: Act) -> (MailboxOwned<Act::Message>, RootEnd<Act>)
+    where
+        Act: Actor,
+        Act::End: IntoResult,
+        <Act::End as IntoResult>::Item: Send + 'static,
+        <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    {
+        let root = RootActor::create(state, false, Some(self.clone()));
+        let (mailbox, end, task) = Self::root_task(root);
+        // if the thread cannot be created, the actor is dropped, as with a refusing spawner
+        if DedicatedThreads.spawn(task).is_err() {
+            trace!("{}: failed to spawn thread for blocking actor, dropping it", mailbox.path());
+        }
+        (mailbox, end)
+    }
+
+    fn spawn_root_actor<Act>(
+        &self,
+        root: RootActor<Act>,
+    ) -> (MailboxOwned<Act::Message>, RootEnd<Act>)
+    where
+        Act: Actor,
+        Act::End: IntoResult,
+        <Act::End as IntoResult>::Item: Send + 'static,
+        <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    {
+        let (mailbox, end, task) = Self::root_task(root);
+        self.spawn_task(task);
+        (mailbox, end)
+    }
+
+    // wrap a root actor into a task which sends its end value to a `RootEnd`
+    fn root_task<Act>(
+        root: RootActor<Act>,
+    ) -> (MailboxOwned<Act::Message>, RootEnd<Act>, BoxedTask)
+    where
+        Act: Actor,
+        Act::End: IntoResult,
+        <Act::End as IntoResult>::Item: Send + 'static,
+        <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    {
+        let mailbox = root.mailbox().clone();
+        let (end_send, end_recv) = oneshot::channel();
+        let task = Box::new(root.then(move |result| {
+            let _ = end_send.send(result);
+            Ok(())
+        }));
+        (mailbox, RootEnd { end_recv }, task)
+    }
+
+    /// The registry shared by all actors of this system.
+    pub fn registry(&self) -> &Registry {
+        &self.inner.registry
+    }
+
+    /// The ids and paths of all live actors of this system.
+    pub fn live_actors(&self) -> Vec<(ActorId, ActorPath)> {
+        lock(&self.inner.live).actors.iter()
+            .map(|(&id, actor)| (id, actor.path.clone()))
+            .collect()
+    }
+
+    /// Kill every actor of this system, and block until they have all terminated,
+    /// or until the timeout elapses.
+    ///
+    /// Actors spawned into the system after this is called are killed immediately.
+    ///
+    /// This blocks the calling thread, so it must not be called from a thread onto
+    /// which the system spawns, which may need to run the actors to termination.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        let kill_signals: Vec<DropSignalArcWeak> = {
+            let mut live = lock(&self.inner.live);
+            live.shutting_down = true;
+            live.actors.values()
+                .map(|actor| actor.kill_signal.clone())
+                .collect()
+        };
+        for kill_signal in kill_signals {
+            kill_signal.send();
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut live = lock(&self.inner.live);
+        while !live.actors.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ShutdownTimeout {
+                    remaining: live.actors.len(),
+                });
+            }
+            live = self.inner.terminated
+                .wait_timeout(live, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        Ok(())
+    }
+
+    /// Spawn a task onto the system's spawner.
+    pub(crate) fn spawn_task(&self, task: BoxedTask) {
+        if self.inner.spawner.spawn(task).is_err() {
+            trace!("actor system spawner refused task, dropping it");
+        }
+    }
+
+    /// Keep track of a newly created actor.
+    pub(crate) fn track(&self, id: ActorId, path: ActorPath, kill_signal: DropSignalArcWeak) {
+        let mut live = lock(&self.inner.live);
+        if live.shutting_down {
+            trace!("{}: actor created during system shutdown, killing it", path);
+            kill_signal.send();
+        }
+        live.actors.insert(id, LiveActor { path, kill_signal });
+    }
+
+    /// Forget an actor, once it has terminated.
+    pub(crate) fn untrack(&self, id: ActorId) {
+        let removed = lock(&self.inner.live).actors.remove(&id).is_some();
+        if removed {
+            self.inner.terminated.notify_all();
+        }
+    }
+}
+
+/// Future which resolves to the end value of a root actor spawned into an
+/// `ActorSystem`.
+pub struct RootEnd<Act>
+where
+    Act: Actor,
+    Act::End: IntoResult,
+{
+    end_recv: oneshot::Receiver<EndResult<Act::End>>,
+}
+
+// the result which a root actor future resolves to
+type EndResult<End> = Result<<End as IntoResult>::Item, <End as IntoResult>::Error>;
+
+impl<Act> Future for RootEnd<Act>
+where
+    Act: Actor,
+    Act::End: IntoResult,
+    <Act::End as IntoResult>::Error: From<AbnormalClose>,
+{
+    type Item = <Act::End as IntoResult>::Item;
+    type Error = <Act::End as IntoResult>::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.end_recv.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // the root actor task was dropped
+            Err(_) => Err(Self::Error::from(AbnormalClose)),
+        }
+    }
+}
+
+/// Error which denotes that actors of a system were still alive when its
+/// shutdown timed out.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ShutdownTimeout {
+    /// The number of actors still alive.
+    pub remaining: usize,
+}
+
+#[cfg(feature = "failure-interop")]
+impl Fail for ShutdownTimeout {}
+
+#[cfg(feature = "failure-interop")]
+impl Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "actor system shutdown timed out with {} actors alive", self.remaining)
+    }
+}
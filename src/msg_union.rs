@@ -1,5 +1,8 @@
 
-use crate::Actor;
+use crate::{Actor, ActorGuardShared};
+
+use std::any::Any;
+use std::time::Instant;
 
 use smallvec::SmallVec;
 
@@ -36,9 +39,39 @@ pub enum MailboxEntry<T: MessageTypeUnion> {
     Mut(<T as MessageTypeUnion>::MutUnion),
 }
 
+/// The runtime value which actually passes through a mailbox channel.
+///
+/// Alongside the user's `MailboxEntry`, this carries crate-internal control entries.
+pub(crate) enum Envelope<T: MessageTypeUnion> {
+    Entry(MailboxEntry<T>),
+    /// An entry which is discarded if it is not processed before the deadline.
+    Expiring(MailboxEntry<T>, Instant),
+    /// A boxed `Inspection<Act>`.
+    ///
+    /// This is type-erased, because mailboxes are not generic over the actor type.
+    Inspect(Box<dyn Any + Send>),
+}
+
+/// Closure which runs under shared access to an actor, as a control entry.
+pub(crate) type Inspection<Act> = Box<dyn FnOnce(ActorGuardShared<Act>) + Send>;
+
+impl<T: MessageTypeUnion> Envelope<T> {
+    /// Unwrap an envelope which is known to hold a `MailboxEntry`.
+    pub(crate) fn into_entry(self) -> MailboxEntry<T> {
+        match self {
+            Envelope::Entry(entry) => entry,
+            Envelope::Expiring(entry, _) => entry,
+            Envelope::Inspect(_) => unreachable!("envelope is not a mailbox entry"),
+        }
+    }
+}
+
 /// Convenience type constructor from `Act: Actor` -> `MailboxEntry<_>`.
 pub type ActorMailboxEntry<Act> = MailboxEntry<<Act as Actor>::Message>;
 
+/// Convenience type constructor from `Act: Actor` -> `Envelope<_>`.
+pub(crate) type ActorEnvelope<Act> = Envelope<<Act as Actor>::Message>;
+
 /// Implement MessageUnion for (), to help partially-written code compile.
 impl MessageTypeUnion for () {
     type SharedUnion = ();
@@ -0,0 +1,16 @@
+use super::{Spawner, BoxedTask};
+
+use futures03::compat::Future01CompatExt;
+use futures03::future::FutureExt;
+
+/// Spawns onto the global async-std executor.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AsyncStdSpawner;
+
+impl Spawner for AsyncStdSpawner {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        // the task is detached when its join handle is dropped
+        ::async_std::task::spawn(task.compat().map(|_| ()));
+        Ok(())
+    }
+}
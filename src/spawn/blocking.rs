@@ -0,0 +1,190 @@
+use super::{Spawner, BoxedTask};
+use crate::util::lock;
+
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use futures::{executor, task};
+
+thread_local! {
+    // whether this thread was created by a blocking spawner, so that its tasks may
+    // block it
+    static BLOCKING_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread may block, without the risk of deadlocking an
+/// executor which runs other tasks on it.
+///
+/// The threads of blocking spawners may always block. Otherwise, this detects
+/// `futures` 0.1 tasks, which includes those of tokio 0.1 and of reflex's own
+/// executors, and with the `async-std` feature, async-std tasks. Tasks of other
+/// executors can't be detected.
+pub(crate) fn may_block() -> bool {
+    if BLOCKING_THREAD.with(Cell::get) {
+        return true;
+    }
+    if task::is_in_task() {
+        return false;
+    }
+    #[cfg(feature = "async-std")]
+    {
+        if ::async_std::task::try_current().is_some() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Spawner which runs each task on a new, dedicated thread.
+///
+/// The thread blocks on the task until it completes. This is meant for actors
+/// which wrap synchronous libraries, and so block in their message handlers. An
+/// actor spawned onto this is dispatched the same way as on any other spawner, so
+/// it receives from the same kind of mailbox, and follows the same access rules.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DedicatedThreads;
+
+impl Spawner for DedicatedThreads {
+    /// Spawn a task onto a new thread.
+    ///
+    /// If the thread cannot be created, the task is returned.
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        // the task is sent to the thread once it exists, so that we still have it
+        // if the thread cannot be created
+        let (task_send, task_recv) = mpsc::channel::<BoxedTask>();
+        let spawned = thread::Builder::new()
+            .name("reflex-blocking".into())
+            .spawn(move || {
+                if let Ok(task) = task_recv.recv() {
+                    run_blocking(task);
+                }
+            });
+        match spawned {
+            Ok(_) => {
+                let _ = task_send.send(task);
+                Ok(())
+            },
+            Err(e) => {
+                trace!("failed to spawn dedicated thread: {}", e);
+                Err(task)
+            },
+        }
+    }
+}
+
+/// Spawner which runs tasks on at most a fixed number of threads, each of which
+/// blocks on one task at a time until it completes.
+///
+/// Since an actor occupies a thread until it terminates, the pool can run at most
+/// as many actors at once as it has threads. Once every thread is occupied, further
+/// tasks are refused, rather than waiting for a thread which may never be freed.
+/// Threads are created as they are needed, and tasks are also refused if a thread
+/// cannot be created.
+///
+/// This handle is `Clone`, and clones refer to the same pool. Once every handle is
+/// dropped, the threads exit after completing their tasks.
+#[derive(Clone)]
+pub struct BlockingPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    max_threads: usize,
+    occupancy: Arc<Mutex<Occupancy>>,
+    // idle threads receive tasks from this
+    task_send: mpsc::Sender<BoxedTask>,
+    task_recv: Arc<Mutex<mpsc::Receiver<BoxedTask>>>,
+}
+
+struct Occupancy {
+    // the number of threads which have been created
+    threads: usize,
+    // the number of tasks which are running, or sent to an idle thread
+    tasks: usize,
+}
+
+// uncounts a thread of a blocking pool when the thread exits, however it exits
+struct ThreadExit(Arc<Mutex<Occupancy>>);
+
+impl Drop for ThreadExit {
+    fn drop(&mut self) {
+        lock(&self.0).threads -= 1;
+    }
+}
+
+impl BlockingPool {
+    /// Create a blocking pool which runs at most the given number of tasks at once.
+    pub fn new(threads: usize) -> Self {
+        let (task_send, task_recv) = mpsc::channel::<BoxedTask>();
+        BlockingPool {
+            inner: Arc::new(PoolInner {
+                max_threads: threads.max(1),
+                occupancy: Arc::new(Mutex::new(Occupancy {
+                    threads: 0,
+                    tasks: 0,
+                })),
+                task_send,
+                task_recv: Arc::new(Mutex::new(task_recv)),
+            }),
+        }
+    }
+}
+
+impl Spawner for BlockingPool {
+    /// Spawn a task onto an idle thread of the pool, creating one if needed.
+    ///
+    /// If every thread is occupied, or a thread cannot be created, the task is
+    /// returned.
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        let inner = &self.inner;
+        let mut occupancy = lock(&inner.occupancy);
+        if occupancy.tasks == inner.max_threads {
+            trace!("every thread of the blocking pool is occupied, refusing task");
+            return Err(task);
+        }
+        if occupancy.tasks == occupancy.threads {
+            let task_recv = inner.task_recv.clone();
+            let thread_occupancy = inner.occupancy.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("reflex-blocking-{}", occupancy.threads))
+                .spawn(move || {
+                    let _exit = ThreadExit(thread_occupancy.clone());
+                    loop {
+                        // the receiver must not be locked while the task runs
+                        let task = lock(&task_recv).recv();
+                        match task {
+                            Ok(task) => {
+                                // a task which panics is dropped, rather than the thread
+                                let run = panic::catch_unwind(AssertUnwindSafe(|| run_blocking(task)));
+                                if run.is_err() {
+                                    trace!("blocking pool task panicked, dropping it");
+                                }
+                                lock(&thread_occupancy).tasks -= 1;
+                            },
+                            Err(_) => break,
+                        }
+                    }
+                });
+            if let Err(e) = spawned {
+                trace!("failed to spawn blocking pool thread: {}", e);
+                return Err(task);
+            }
+            occupancy.threads += 1;
+        }
+        occupancy.tasks += 1;
+        // the pool holds a receiver, so this cannot fail
+        let _ = inner.task_send.send(task);
+        Ok(())
+    }
+}
+
+/// Block the current thread, which is a thread of a blocking spawner, on a task
+/// until it completes.
+fn run_blocking(task: BoxedTask) {
+    BLOCKING_THREAD.with(|blocking| blocking.set(true));
+    let _ = executor::spawn(task).wait_future();
+}
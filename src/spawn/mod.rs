@@ -0,0 +1,59 @@
+use futures::Future;
+use futures::future::{Executor, ExecuteError};
+
+use std::sync::Arc;
+
+/// Spawning onto tokio executors.
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+/// Spawning onto the async-std executor.
+#[cfg(feature = "async-std")]
+pub mod async_std;
+
+/// Spawning onto dedicated threads, for actors which block.
+pub mod blocking;
+
+/// Reflex's built-in thread pool.
+#[cfg(feature = "thread-pool")]
+pub mod thread_pool;
+
+/// A type-erased task, which can be spawned.
+pub type BoxedTask = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// Mechanism with which reflex spawns tasks.
+///
+/// Reflex uses a spawner wherever it spawns tasks itself, such as the routines
+/// of actors spawned by an `ActorSystem`, and the handlers of shared messages
+/// which are processed in parallel.
+pub trait Spawner: Send + Sync + 'static {
+    /// Spawn a task.
+    ///
+    /// If the task cannot be spawned, it is returned.
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask>;
+}
+
+impl<S: Spawner + ?Sized> Spawner for Arc<S> {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        (**self).spawn(task)
+    }
+}
+
+impl<S: Spawner + ?Sized> Spawner for Box<S> {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        (**self).spawn(task)
+    }
+}
+
+/// Adapter which spawns onto any `futures` executor.
+#[derive(Copy, Clone, Debug)]
+pub struct ExecutorSpawner<E>(pub E);
+
+impl<E> Spawner for ExecutorSpawner<E>
+where
+    E: Executor<BoxedTask> + Send + Sync + 'static,
+{
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        self.0.execute(task).map_err(ExecuteError::into_future)
+    }
+}
@@ -0,0 +1,316 @@
+use super::{Spawner, BoxedTask};
+use crate::{
+    Actor,
+    mailbox::MailboxOwned,
+    manage::{AbnormalClose, ActorSystem, IntoResult},
+    util::lock,
+};
+
+use std::{
+    cell::RefCell,
+    iter,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc,
+        Weak,
+        Mutex,
+        Condvar,
+        atomic::{self, AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crossbeam_deque::{Injector, Stealer, Worker, Steal};
+use futures::{
+    Future,
+    Async,
+    executor::{self, Spawn, Notify, NotifyHandle},
+};
+
+/// Run an actor tree on a new thread pool, blocking until its root actor
+/// resolves to its end value.
+///
+/// The root is spawned into an actor system over the pool, so that it can spawn
+/// subordinates, and the given closure is then called with the root's mailbox,
+/// with which to send it its first messages.
+///
+/// The pool has a thread per available CPU, and is shut down once every actor of
+/// the tree has terminated.
+pub fn run<Act, F>(state: Act, start: F) -> Result<
+    <Act::End as IntoResult>::Item,
+    <Act::End as IntoResult>::Error,
+>
+where
+    Act: Actor,
+    Act::End: IntoResult,
+    <Act::End as IntoResult>::Item: Send + 'static,
+    <Act::End as IntoResult>::Error: From<AbnormalClose> + Send + 'static,
+    F: FnOnce(MailboxOwned<Act::Message>),
+{
+    let system = ActorSystem::new(ThreadPool::default());
+    let (mailbox, end) = system.spawn_root(state);
+    start(mailbox);
+    end.wait()
+}
+
+/// A work-stealing pool of threads, onto which tasks can be spawned.
+///
+/// Each thread has its own queue of tasks, and steals tasks from the other
+/// threads when its queue is empty. Tasks which are woken by a thread of the
+/// pool are queued onto that thread, to be run next. For actors, this means that
+/// an actor which was sent a message is likely to process it on the thread which
+/// sent it, while the message is still in cache.
+///
+/// This handle is `Clone`, and clones refer to the same pool. Once every handle
+/// is dropped, the threads exit, dropping any tasks which are still pending.
+#[derive(Clone)]
+pub struct ThreadPool {
+    handle: Arc<PoolHandle>,
+}
+
+// the last handle to the pool shuts it down when dropped
+struct PoolHandle {
+    shared: Arc<PoolShared>,
+}
+
+struct PoolShared {
+    // queue for tasks spawned or woken outside of the pool's threads
+    injector: Injector<Arc<PoolTask>>,
+    stealers: Vec<Stealer<Arc<PoolTask>>>,
+
+    shut_down: AtomicBool,
+
+    // number of threads which are about to sleep, or sleeping
+    sleepers: AtomicUsize,
+    sleep_lock: Mutex<()>,
+    wake: Condvar,
+}
+
+thread_local! {
+    // the local queue of the pool thread which this is, if any
+    static LOCAL: RefCell<Option<LocalQueue>> = const { RefCell::new(None) };
+}
+
+struct LocalQueue {
+    // identifies the pool by its shared state
+    pool: *const PoolShared,
+    worker: Worker<Arc<PoolTask>>,
+}
+
+// states of a pool task
+const IDLE: usize = 0;
+const SCHEDULED: usize = 1;
+const RUNNING: usize = 2;
+const NOTIFIED: usize = 3;
+const COMPLETE: usize = 4;
+
+struct PoolTask {
+    // only locked by the thread which is running the task
+    spawn: Mutex<Option<Spawn<BoxedTask>>>,
+    state: AtomicUsize,
+    pool: Weak<PoolShared>,
+}
+
+impl ThreadPool {
+    /// Create a thread pool with the given number of threads.
+    pub fn new(threads: usize) -> Self {
+        let workers: Vec<Worker<Arc<PoolTask>>> = (0..threads.max(1))
+            .map(|_| Worker::new_lifo())
+            .collect();
+        let shared = Arc::new(PoolShared {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            shut_down: AtomicBool::new(false),
+            sleepers: AtomicUsize::new(0),
+            sleep_lock: Mutex::new(()),
+            wake: Condvar::new(),
+        });
+        for (i, worker) in workers.into_iter().enumerate() {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("reflex-pool-{}", i))
+                .spawn(move || shared.work(worker))
+                .expect("failed to spawn thread pool thread");
+        }
+        ThreadPool {
+            handle: Arc::new(PoolHandle { shared }),
+        }
+    }
+}
+
+impl Default for ThreadPool {
+    /// Create a thread pool with a thread per available CPU.
+    fn default() -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Self::new(threads)
+    }
+}
+
+impl Spawner for ThreadPool {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        let shared = &self.handle.shared;
+        let task = Arc::new(PoolTask {
+            spawn: Mutex::new(Some(executor::spawn(task))),
+            state: AtomicUsize::new(SCHEDULED),
+            pool: Arc::downgrade(shared),
+        });
+        shared.schedule(task);
+        Ok(())
+    }
+}
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        self.shared.shut_down.store(true, Ordering::SeqCst);
+        let _guard = lock(&self.shared.sleep_lock);
+        self.shared.wake.notify_all();
+    }
+}
+
+impl PoolShared {
+    fn schedule(&self, task: Arc<PoolTask>) {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return;
+        }
+
+        // queue the task onto this thread, if this is one of the pool's threads
+        let task = LOCAL.with(|local| match *local.borrow() {
+            Some(ref local) if std::ptr::eq(local.pool, self) => {
+                local.worker.push(task);
+                None
+            },
+            _ => Some(task),
+        });
+        if let Some(task) = task {
+            self.injector.push(task);
+        }
+
+        // either we observe the sleeper, or the sleeper observes the task
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.sleep_lock);
+            self.wake.notify_one();
+        }
+    }
+
+    // the routine of a pool thread
+    fn work(&self, worker: Worker<Arc<PoolTask>>) {
+        LOCAL.with(|local| {
+            *local.borrow_mut() = Some(LocalQueue {
+                pool: self as *const PoolShared,
+                worker,
+            });
+        });
+
+        while !self.shut_down.load(Ordering::SeqCst) {
+            // the local queue must not be borrowed while the task runs, since the
+            // task may wake other tasks onto it
+            let task = LOCAL.with(|local| match *local.borrow() {
+                Some(ref local) => self.find_task(&local.worker),
+                None => None,
+            });
+            match task {
+                Some(task) => task.run(),
+                None => self.sleep(),
+            }
+        }
+
+        // drop the pending tasks of this thread
+        LOCAL.with(|local| local.borrow_mut().take());
+    }
+
+    fn find_task(&self, local: &Worker<Arc<PoolTask>>) -> Option<Arc<PoolTask>> {
+        local.pop().or_else(|| iter::repeat_with(|| {
+            self.injector.steal_batch_and_pop(local)
+                .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+        })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success))
+    }
+
+    fn sleep(&self) {
+        let guard = lock(&self.sleep_lock);
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        let idle = self.injector.is_empty()
+            && self.stealers.iter().all(Stealer::is_empty);
+        if idle && !self.shut_down.load(Ordering::SeqCst) {
+            let _guard = self.wake.wait(guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl PoolTask {
+    // poll the task until it completes, or yields without being notified
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let notify = NotifyHandle::from(Arc::new(TaskWaker(self.clone())));
+        let mut spawn = lock(&self.spawn);
+        loop {
+            let done = match *spawn {
+                // a task which panics is dropped, rather than the thread and the
+                // tasks queued on it
+                Some(ref mut spawn) => match panic::catch_unwind(AssertUnwindSafe(|| {
+                    spawn.poll_future_notify(&notify, 0)
+                })) {
+                    Ok(Ok(Async::NotReady)) => false,
+                    Ok(_) => true,
+                    Err(_) => {
+                        trace!("pool task panicked, dropping it");
+                        true
+                    },
+                },
+                None => true,
+            };
+            if done {
+                *spawn = None;
+                self.state.store(COMPLETE, Ordering::SeqCst);
+                return;
+            }
+            match self.state.compare_exchange(
+                RUNNING,
+                IDLE,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return,
+                // notified while running, so poll again
+                Err(_) => self.state.store(RUNNING, Ordering::SeqCst),
+            }
+        }
+    }
+}
+
+// notification handle of a pool task, which keeps the task alive
+struct TaskWaker(Arc<PoolTask>);
+
+impl Notify for TaskWaker {
+    fn notify(&self, _: usize) {
+        let task = &self.0;
+        let mut state = task.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match task.state.compare_exchange(
+                state,
+                next,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+        if state == IDLE {
+            if let Some(pool) = task.pool.upgrade() {
+                pool.schedule(task.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use super::{Spawner, BoxedTask};
+
+use futures::future::{Executor, ExecuteError};
+use ::tokio::runtime::TaskExecutor;
+use ::tokio::executor::DefaultExecutor;
+
+impl Spawner for TaskExecutor {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        self.execute(task).map_err(ExecuteError::into_future)
+    }
+}
+
+/// Spawns onto the tokio executor of the thread which spawns the task.
+impl Spawner for DefaultExecutor {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        self.execute(task).map_err(ExecuteError::into_future)
+    }
+}
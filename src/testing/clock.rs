@@ -0,0 +1,111 @@
+use crate::util::{
+    lock,
+    timer::Deadlines,
+};
+
+use std::{
+    cell::RefCell,
+    sync::{Arc, Weak, Mutex},
+    time::Instant,
+};
+
+use futures::task::AtomicTask;
+
+thread_local! {
+    // the clock which reflex's timing features consult on this thread
+    static CURRENT: RefCell<Weak<ClockInner>> = const { RefCell::new(Weak::new()) };
+}
+
+/// The mock clock which is current on this thread, if any.
+pub(crate) fn current() -> Option<MockClock> {
+    CURRENT.with(|current| current.borrow().upgrade())
+        .map(|inner| MockClock { inner })
+}
+
+/// Virtual time, which only passes when a test advances it.
+///
+/// Each `TestExecutor` has a mock clock. Reflex's timing features, such as message
+/// expiry and timed sends, consult the mock clock instead of the system clock while
+/// the executor polls its tasks, including the future of `block_on`. Outside of
+/// the executor, deadlines for the mock clock can be computed from its `now`.
+/// Its time starts at the real time at which the executor was created.
+///
+/// Blocking features, such as blocking sends, still wait in real time.
+///
+/// This handle is `Clone`, and clones refer to the same clock.
+#[derive(Clone)]
+pub struct MockClock {
+    inner: Arc<ClockInner>,
+}
+
+struct ClockInner {
+    state: Mutex<ClockState>,
+}
+
+struct ClockState {
+    now: Instant,
+    deadlines: Deadlines,
+}
+
+impl MockClock {
+    /// Create a clock, which starts at the current real time.
+    pub(crate) fn new() -> Self {
+        MockClock {
+            inner: Arc::new(ClockInner {
+                state: Mutex::new(ClockState {
+                    now: Instant::now(),
+                    deadlines: Deadlines::default(),
+                }),
+            }),
+        }
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> Instant {
+        lock(&self.inner.state).now
+    }
+
+    /// The earliest deadline which a task is waiting for, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        lock(&self.inner.state).deadlines.next()
+    }
+
+    /// Move the time forward to the given instant, if it is later than the current
+    /// time, and notify the tasks whose deadlines have then passed.
+    pub(crate) fn advance_to(&self, instant: Instant) {
+        let mut state = lock(&self.inner.state);
+        if instant > state.now {
+            state.now = instant;
+        }
+        let now = state.now;
+        state.deadlines.fire(now);
+    }
+
+    /// The number of deadlines which tasks are waiting for.
+    #[cfg(test)]
+    pub(crate) fn pending(&self) -> usize {
+        lock(&self.inner.state).deadlines.len()
+    }
+
+    /// Register a task to be notified once the time reaches a deadline.
+    pub(crate) fn register(&self, deadline: Instant, task: Arc<AtomicTask>) {
+        lock(&self.inner.state).deadlines.register(deadline, task);
+    }
+
+    /// Make this the current clock of this thread, until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> Entered {
+        let previous = CURRENT.with(|current| current.replace(Arc::downgrade(&self.inner)));
+        Entered { previous }
+    }
+}
+
+/// Guard which restores the previous current clock of the thread when dropped.
+pub(crate) struct Entered {
+    previous: Weak<ClockInner>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.clone());
+    }
+}
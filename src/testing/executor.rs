@@ -0,0 +1,267 @@
+use super::clock::MockClock;
+use crate::{
+    spawn::{Spawner, BoxedTask},
+    util::lock,
+};
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    Future,
+    Async,
+    executor::{self, Spawn, Notify, NotifyHandle},
+};
+
+thread_local! {
+    // whether a test executor is polling a task on this thread
+    static STEPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether actor routines should process only one message each time they are
+/// polled, because they are being stepped by a `TestExecutor`.
+pub(crate) fn stepping() -> bool {
+    STEPPING.with(Cell::get)
+}
+
+// the notify id of the future which `block_on` is polling
+const BLOCKED_ON: usize = usize::MAX;
+
+/// Deterministic, single-threaded executor for tests.
+///
+/// Tasks are only polled when the test calls `step`, `run_until_idle` or
+/// `block_on`, on the test's thread, in the order in which they were notified.
+/// While an actor routine is polled by this executor, it processes at most one
+/// message before yielding, so a test can step through an actor's messages one
+/// at a time, and inspect its state in between.
+///
+/// This implements `Spawner`, so an `ActorSystem` can be created on it.
+///
+/// The executor has a `MockClock`, which reflex's timing features consult while
+/// the executor polls its tasks. Time only passes on it when the test calls
+/// `advance`.
+///
+/// This handle is `Clone`, and clones refer to the same executor.
+#[derive(Clone)]
+pub struct TestExecutor {
+    inner: Arc<ExecutorInner>,
+}
+
+struct ExecutorInner {
+    tasks: Mutex<Vec<TaskSlot>>,
+    ready: Arc<ReadyQueue>,
+    clock: MockClock,
+}
+
+enum TaskSlot {
+    Vacant,
+    Pending(Spawn<BoxedTask>),
+    // the task has been taken out of its slot to be polled
+    Running,
+}
+
+// queue of the ids of the notified tasks
+#[derive(Default)]
+struct ReadyQueue {
+    ready: Mutex<VecDeque<usize>>,
+}
+
+impl Notify for ReadyQueue {
+    fn notify(&self, id: usize) {
+        let mut ready = lock(&self.ready);
+        if !ready.contains(&id) {
+            ready.push_back(id);
+        }
+    }
+}
+
+impl TestExecutor {
+    /// Create an executor with no tasks.
+    pub fn new() -> Self {
+        TestExecutor {
+            inner: Arc::new(ExecutorInner {
+                tasks: Mutex::new(Vec::new()),
+                ready: Arc::default(),
+                clock: MockClock::new(),
+            }),
+        }
+    }
+
+    /// The executor's mock clock.
+    pub fn clock(&self) -> &MockClock {
+        &self.inner.clock
+    }
+
+    /// Advance the mock clock by the given duration, running the tasks which become
+    /// ready until idle.
+    ///
+    /// The clock stops at each deadline which passes in that duration, in order, and
+    /// the tasks waiting for it run until idle before the clock moves on. So timers
+    /// which are registered by those tasks also fire, if they are due within the
+    /// duration.
+    pub fn advance(&self, duration: Duration) {
+        let clock = &self.inner.clock;
+        let target = clock.now() + duration;
+        self.run_until_idle();
+        while clock.now() < target {
+            self.advance_towards(target);
+        }
+    }
+
+    /// Advance the mock clock to the earliest deadline before the target, or to the
+    /// target if there is none, and run the tasks which become ready until idle.
+    pub(crate) fn advance_towards(&self, target: Instant) {
+        let clock = &self.inner.clock;
+        let next = clock.next_deadline()
+            .filter(|&next| next < target)
+            .unwrap_or(target);
+        clock.advance_to(next);
+        self.run_until_idle();
+    }
+
+    /// Poll the next notified task once.
+    ///
+    /// Returns whether there was a task to poll.
+    pub fn step(&self) -> bool {
+        loop {
+            let id = match lock(&self.inner.ready.ready).pop_front() {
+                Some(id) => id,
+                None => return false,
+            };
+            if self.poll_task(id) {
+                return true;
+            }
+        }
+    }
+
+    /// Poll notified tasks until none are left.
+    ///
+    /// Once this returns, no task of this executor can make progress until it is
+    /// notified again, so every actor on it has processed every message which it
+    /// is able to, and every guard which is held by a task of this executor is
+    /// held by a task which is waiting for something outside of the executor.
+    ///
+    /// This deliberately does not promise that no guards are held. A guard may be
+    /// held by the test itself, or by a task waiting for something outside of the
+    /// executor, such as a timer or another executor, and the executor cannot
+    /// release those guards, so waiting for them could hang the test. Tests which
+    /// need an actor to be unlocked should release such guards first.
+    ///
+    /// Returns the number of times a task was polled.
+    pub fn run_until_idle(&self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Run the executor's tasks until the given future resolves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the executor becomes idle before the future resolves, since the
+    /// future could then never resolve.
+    pub fn block_on<F: Future>(&self, future: F) -> Result<F::Item, F::Error> {
+        let notify = NotifyHandle::from(self.inner.ready.clone());
+        let mut future = executor::spawn(future);
+        let mut poll_future = true;
+        loop {
+            if poll_future {
+                let result = self.enter(|| future.poll_future_notify(&notify, BLOCKED_ON));
+                match result {
+                    Ok(Async::NotReady) => (),
+                    Ok(Async::Ready(item)) => return Ok(item),
+                    Err(e) => return Err(e),
+                };
+            }
+            let id = lock(&self.inner.ready.ready).pop_front();
+            poll_future = match id {
+                Some(BLOCKED_ON) => true,
+                Some(id) => {
+                    self.poll_task(id);
+                    false
+                },
+                None => panic!("TestExecutor::block_on: executor is idle, so the future can never resolve"),
+            };
+        }
+    }
+
+    /// Whether no task of this executor has been notified.
+    pub fn is_idle(&self) -> bool {
+        lock(&self.inner.ready.ready).is_empty()
+    }
+
+    // poll the task with the given id, unless it has completed
+    fn poll_task(&self, id: usize) -> bool {
+        // the task must not be in its slot while it runs, since it may spawn tasks
+        let mut task = {
+            let mut tasks = lock(&self.inner.tasks);
+            match tasks.get_mut(id) {
+                Some(slot @ &mut TaskSlot::Pending(_)) => {
+                    match std::mem::replace(slot, TaskSlot::Running) {
+                        TaskSlot::Pending(task) => task,
+                        _ => unreachable!(),
+                    }
+                },
+                // a stale notification of a completed task
+                _ => return false,
+            }
+        };
+
+        let notify = NotifyHandle::from(self.inner.ready.clone());
+        let done = self.enter(|| !matches!(
+            task.poll_future_notify(&notify, id),
+            Ok(Async::NotReady)
+        ));
+
+        lock(&self.inner.tasks)[id] = if done {
+            TaskSlot::Vacant
+        } else {
+            TaskSlot::Pending(task)
+        };
+        true
+    }
+
+    // run a closure with the stepping flag set and the clock current, restoring
+    // them even if the closure panics
+    fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(bool);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                STEPPING.with(|stepping| stepping.set(self.0));
+            }
+        }
+
+        let _restore = Restore(STEPPING.with(|stepping| stepping.replace(true)));
+        let _entered = self.inner.clock.enter();
+        f()
+    }
+}
+
+impl Spawner for TestExecutor {
+    fn spawn(&self, task: BoxedTask) -> Result<(), BoxedTask> {
+        let mut tasks = lock(&self.inner.tasks);
+        let id = match tasks.iter().position(|slot| matches!(*slot, TaskSlot::Vacant)) {
+            Some(id) => id,
+            None => {
+                tasks.push(TaskSlot::Vacant);
+                tasks.len() - 1
+            },
+        };
+        tasks[id] = TaskSlot::Pending(executor::spawn(task));
+        self.inner.ready.notify(id);
+        Ok(())
+    }
+}
+
+impl Default for TestExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,13 @@
+
+/// Deterministic, single-threaded executor for tests.
+pub mod executor;
+
+/// Fake actors which record the messages sent to them.
+pub mod probe;
+
+/// Virtual time for tests of timing features.
+pub mod clock;
+
+pub use self::executor::TestExecutor;
+pub use self::clock::MockClock;
+pub use self::probe::{TestProbe, Received};
@@ -0,0 +1,275 @@
+use crate::{
+    Actor,
+    ActorGuardShared,
+    ActorGuardMut,
+    internal::{
+        queue::{MsgQueue, MsgQueueEntry},
+        create::{create_actor, create_channels},
+        supervise::{SupervisorLink, EndSignalSend},
+    },
+    mailbox::{MailboxOwned, MailboxWeak},
+    manage::{ActorId, Registry, SubordinateActor, SubordinateHandle},
+    msg_union::{Message, MessageTypeUnion, MailboxEntry},
+    util::{
+        park::block_until,
+        drop_signal::drop_signal_channel,
+    },
+};
+use super::TestExecutor;
+
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    Stream,
+    executor,
+    sync::mpsc,
+};
+use smallvec::SmallVec;
+
+/// Fake actor, which records the messages sent to it, to be asserted on by a test.
+///
+/// A probe has a real mailbox, which can be given to the actor under test in place
+/// of a collaborator. It can also act as the supervisor of actors which it manages,
+/// in which case it records their `SubordinateEnd` values.
+///
+/// A probe does not need to be spawned. Its assertions block the calling thread
+/// until a message arrives, or until the probe's timeout elapses, in which case they
+/// panic. When the actor under test runs on a `TestExecutor`, the probe should be
+/// given that executor with `with_executor`, in which case its assertions instead
+/// run the executor until idle, and advance its mock clock up to the timeout,
+/// until a message arrives.
+///
+/// Messages are recorded as they were sent, so shared batches are not split, and
+/// expiring messages are recorded even once expired. Inspections are discarded,
+/// since there is no actor state to inspect. As with an actor, end values of
+/// subordinates are received before mailbox entries which are already queued.
+pub struct TestProbe<T: MessageTypeUnion, S: Message = ()> {
+    msg_recv: MsgQueue<ProbeActor<T, S>>,
+    mailbox: MailboxOwned<T>,
+    subord_end_send: EndSignalSend<S>,
+    registry: Registry,
+    timeout: Duration,
+    // the executor whose clock the probe waits on, if any
+    executor: Option<TestExecutor>,
+}
+
+/// A message which was received by a `TestProbe`.
+pub enum Received<T: MessageTypeUnion, S> {
+    /// A mailbox entry, sent through the probe's mailbox.
+    Entry(MailboxEntry<T>),
+    /// The end value of an actor which the probe manages.
+    SubordinateEnd(ActorId, S),
+}
+
+impl<T: MessageTypeUnion, S> Received<T, S> {
+    fn describe(&self) -> &'static str {
+        match *self {
+            Received::Entry(MailboxEntry::Shared(_)) => "shared message",
+            Received::Entry(MailboxEntry::Mut(_)) => "mut message",
+            Received::SubordinateEnd(..) => "subordinate end",
+        }
+    }
+}
+
+// the actor type which the probe's channels are created for, which is never dispatched
+struct ProbeActor<T, S>(PhantomData<fn() -> (T, S)>);
+
+impl<T: MessageTypeUnion, S: Message> Actor for ProbeActor<T, S> {
+    type Message = T;
+    type End = ();
+    type SubordinateEnd = S;
+
+    fn handle_msg_shared(_: ActorGuardShared<Self>, _: T::SharedUnion) {
+        unreachable!("probe actor is never dispatched")
+    }
+
+    fn handle_msg_mut(_: ActorGuardMut<Self>, _: T::MutUnion) {
+        unreachable!("probe actor is never dispatched")
+    }
+
+    fn handle_subordinate_end(_: ActorGuardMut<Self>, _: S) {
+        unreachable!("probe actor is never dispatched")
+    }
+}
+
+impl<T: MessageTypeUnion, S: Message> TestProbe<T, S> {
+    /// Create a probe, which waits up to 3 seconds for each expected message.
+    pub fn new() -> Self {
+        // a probe has no manager, so its end signal is unused
+        let (end_signal_send, _) = mpsc::unbounded();
+        let link = SupervisorLink::<ProbeActor<T, S>>::root(
+            end_signal_send,
+            false,
+            Registry::new(),
+            None,
+        );
+        let channels = create_channels(&link);
+        let mailbox = MailboxOwned::new_owned(
+            channels.inbox.mailbox,
+            channels.kill_signal_send,
+        );
+
+        TestProbe {
+            msg_recv: channels.inbox.msg_recv,
+            mailbox,
+            subord_end_send: channels.inbox.subord_end_signal_send,
+            registry: link.registry,
+            timeout: Duration::from_secs(3),
+            executor: None,
+        }
+    }
+
+    /// Set how long the probe waits for each expected message.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait for messages by running the given executor, and measure the probe's
+    /// waits with its mock clock, rather than blocking in real time.
+    pub fn with_executor(mut self, executor: &TestExecutor) -> Self {
+        self.executor = Some(executor.clone());
+        self
+    }
+
+    /// The probe's mailbox.
+    pub fn mailbox(&self) -> &MailboxOwned<T> {
+        &self.mailbox
+    }
+
+    /// Clone and downgrade the probe's mailbox.
+    pub fn mailbox_handle(&self) -> MailboxWeak<T> {
+        self.mailbox.clone().downgrade()
+    }
+
+    /// Create a subordinate actor, with this probe as its manager.
+    ///
+    /// Returns a future which must be spawned, the subordinate mailbox, and a
+    /// handle with which the test can stop the subordinate. The subordinate's end
+    /// value is received by the probe.
+    pub fn manage<Sub>(&self, state: Sub) -> (
+        SubordinateActor<Sub>,
+        MailboxOwned<Sub::Message>,
+        SubordinateHandle<Sub>,
+    )
+    where
+        Sub: Actor<End = S>
+    {
+        // the probe does not track its subordinates, so their termination signals are unused
+        let (terminated_send, _) = drop_signal_channel();
+        let id = ActorId::next();
+        let link = SupervisorLink {
+            id,
+            path: self.mailbox.path().child(&id.to_string()),
+            end_signal_send: self.subord_end_send.clone(),
+            structured: false,
+            terminated_send,
+            registry: self.registry.clone(),
+            system: None,
+        };
+        let created = create_actor(state, link);
+        let drop_signal = created.kill_signal_send;
+        let mailbox = MailboxOwned::new_owned(
+            created.mailbox,
+            drop_signal.clone(),
+        );
+
+        (
+            SubordinateActor::new(created.state),
+            mailbox,
+            SubordinateHandle::new(
+                drop_signal.downgrade(),
+                created.stop_send,
+            ),
+        )
+    }
+
+    /// Wait up to the given duration for the next message received by the probe.
+    ///
+    /// If the probe has an executor, the duration is measured by its mock clock.
+    pub fn recv(&mut self, within: Duration) -> Option<Received<T, S>> {
+        match self.executor.clone() {
+            Some(executor) => {
+                let deadline = executor.clock().now() + within;
+                executor.run_until_idle();
+                loop {
+                    // the executor is idle, so the messages which its tasks have
+                    // sent are already queued, and need not be waited for
+                    let received = self.recv_until(Instant::now());
+                    if received.is_some() || executor.clock().now() >= deadline {
+                        return received;
+                    }
+                    executor.advance_towards(deadline);
+                }
+            },
+            None => self.recv_until(Instant::now() + within),
+        }
+    }
+
+    // block until the next message, or until the deadline passes in real time
+    fn recv_until(&mut self, deadline: Instant) -> Option<Received<T, S>> {
+        loop {
+            let mut next = executor::spawn(self.msg_recv.by_ref().into_future());
+            let entry = match block_until(&mut next, Some(deadline)) {
+                Some(Ok((entry, _))) => entry,
+                Some(Err(_)) | None => return None,
+            };
+            match entry {
+                Some(MsgQueueEntry::MailboxEntry(entry, _)) => return Some(Received::Entry(entry)),
+                Some(MsgQueueEntry::SubordinateEnd(id, end)) => return Some(Received::SubordinateEnd(id, end)),
+                Some(MsgQueueEntry::Inspect(_)) => trace!("{}: probe discarding inspection", self.mailbox.path()),
+                // the probe holds its own mailbox, so its queue never ends
+                None => return None,
+            };
+        }
+    }
+
+    /// Assert that the next message received by the probe is a shared batch, and
+    /// return its messages.
+    pub fn expect_shared(&mut self) -> SmallVec<[T::SharedUnion; 4]> {
+        match self.expect("shared message") {
+            Received::Entry(MailboxEntry::Shared(msg_vec)) => msg_vec,
+            received => panic!("expected shared message, received {}", received.describe()),
+        }
+    }
+
+    /// Assert that the next message received by the probe is a mut message, and
+    /// return it.
+    pub fn expect_mut(&mut self) -> T::MutUnion {
+        match self.expect("mut message") {
+            Received::Entry(MailboxEntry::Mut(msg)) => msg,
+            received => panic!("expected mut message, received {}", received.describe()),
+        }
+    }
+
+    /// Assert that the next message received by the probe is the end value of an
+    /// actor which it manages, and return the actor's id and end value.
+    pub fn expect_subordinate_end(&mut self) -> (ActorId, S) {
+        match self.expect("subordinate end") {
+            Received::SubordinateEnd(id, end) => (id, end),
+            received => panic!("expected subordinate end, received {}", received.describe()),
+        }
+    }
+
+    /// Assert that the probe does not receive a message within the given duration.
+    pub fn expect_no_message(&mut self, within: Duration) {
+        if let Some(received) = self.recv(within) {
+            panic!("expected no message, received {}", received.describe());
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Received<T, S> {
+        let timeout = self.timeout;
+        self.recv(timeout)
+            .unwrap_or_else(|| panic!("expected {}, received nothing within {:?}", expected, timeout))
+    }
+}
+
+impl<T: MessageTypeUnion, S: Message> Default for TestProbe<T, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,11 @@
 
 use std::{
-    sync::Arc,
+    sync::{Arc, Weak, Mutex},
     convert::Infallible,
 };
 
+use super::lock;
+
 use futures::{
     Future,
     Poll,
@@ -15,7 +17,7 @@ use futures::{
 ///
 /// A user can also send the signal early, without dropping.
 pub struct DropSignalSend {
-    send: Option<oneshot::Sender<()>>
+    send: Mutex<Option<oneshot::Sender<()>>>
 }
 
 /// Paired with a `DropSignalSend`, completes when the other end is dropped.
@@ -33,13 +35,29 @@ impl Drop for DropSignalSend {
 }
 
 impl DropSignalSend {
-    pub fn send(&mut self) {
-        if let Some(send) = self.send.take() {
+    /// Send the signal, if it has not already been sent.
+    ///
+    /// This can be called through a shared reference, so that a shared signal can
+    /// be sent early.
+    pub fn send(&self) {
+        let send = lock(&self.send).take();
+        if let Some(send) = send {
             let _ = send.send(());
         }
     }
 }
 
+impl DropSignalRecv {
+    /// Whether the signal has been sent, checked without registering the current
+    /// task for notification.
+    pub fn try_recv(&mut self) -> bool {
+        if !self.dead {
+            self.dead = !matches!(self.recv.try_recv(), Ok(None));
+        }
+        self.dead
+    }
+}
+
 impl Future for DropSignalRecv {
     type Item = ();
     type Error = Infallible;
@@ -63,17 +81,15 @@ impl Future for DropSignalRecv {
 #[derive(Clone)]
 pub struct DropSignalArc(pub Arc<DropSignalSend>);
 
-/*
 /// Convenience wrapper around `std::sync::Weak<DropSignalSend>`.
 #[derive(Clone)]
-pub struct DropSignalArcWeak(pub WeakArc<DropSignalSend>);
-*/
+pub struct DropSignalArcWeak(pub Weak<DropSignalSend>);
 
 /// Create a new paired `DropSignalSend` and `DropSignalRecv`.
 pub fn drop_signal_channel() -> (DropSignalSend, DropSignalRecv) {
     let (send, recv) = oneshot::channel();
     (
-        DropSignalSend { send: Some(send) },
+        DropSignalSend { send: Mutex::new(Some(send)) },
         DropSignalRecv { recv, dead: false },
     )
 }
@@ -85,11 +101,18 @@ impl DropSignalSend {
     }
 }
 
-/*
 impl DropSignalArc {
-    /// Downgrade this into a weak reference counter.
-    pub fn downgrade(self) -> DropSignalArcWeak {
-
+    /// Create a weak reference counter to the same signal.
+    pub fn downgrade(&self) -> DropSignalArcWeak {
+        DropSignalArcWeak(Arc::downgrade(&self.0))
     }
 }
-*/
\ No newline at end of file
+
+impl DropSignalArcWeak {
+    /// Send the signal early, if it has not already been sent.
+    pub fn send(&self) {
+        if let Some(signal) = self.0.upgrade() {
+            signal.send();
+        }
+    }
+}
\ No newline at end of file
@@ -1,3 +1,20 @@
 
 /// Async mechanism for mailbox ownership of actors.
-pub mod drop_signal;
\ No newline at end of file
+pub mod drop_signal;
+
+/// Blocking of threads on futures, outside of executors.
+pub mod park;
+
+/// Timing out of futures, independently of executors.
+pub mod timer;
+
+/// Synchronization primitives, which can be swapped for loom's.
+pub mod sync;
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock a mutex, ignoring poisoning, since reflex does not leave its state
+/// inconsistent across panics.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
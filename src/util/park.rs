@@ -0,0 +1,48 @@
+use std::{
+    sync::Arc,
+    thread::{self, Thread},
+    time::Instant,
+};
+
+use futures::{
+    Future,
+    Async,
+    executor::{Spawn, Notify, NotifyHandle},
+};
+
+/// Notification handle which unparks a blocked thread.
+struct ThreadNotify(Thread);
+
+impl Notify for ThreadNotify {
+    fn notify(&self, _: usize) {
+        self.0.unpark();
+    }
+}
+
+/// Block the current thread on a future, until it resolves or the deadline passes.
+///
+/// Returns `None` if the deadline passes first, in which case the future is left
+/// unresolved.
+pub fn block_until<F: Future>(
+    spawn: &mut Spawn<F>,
+    deadline: Option<Instant>,
+) -> Option<Result<F::Item, F::Error>> {
+    let notify = NotifyHandle::from(Arc::new(ThreadNotify(thread::current())));
+    loop {
+        match spawn.poll_future_notify(&notify, 0) {
+            Ok(Async::NotReady) => (),
+            Ok(Async::Ready(item)) => return Some(Ok(item)),
+            Err(e) => return Some(Err(e)),
+        };
+        match deadline {
+            None => thread::park(),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                thread::park_timeout(deadline - now);
+            },
+        };
+    }
+}
@@ -0,0 +1,9 @@
+
+/// Atomic types, which are loom's when model checking.
+pub mod atomic {
+    #[cfg(not(loom))]
+    pub use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+
+    #[cfg(loom)]
+    pub use loom::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+}
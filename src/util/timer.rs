@@ -0,0 +1,214 @@
+use super::lock;
+#[cfg(any(test, feature = "testing"))]
+use crate::testing::clock;
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::{Arc, Mutex, Condvar, OnceLock},
+    thread,
+    time::Instant,
+};
+
+use futures::{
+    Future,
+    Poll,
+    Async,
+    task::AtomicTask,
+};
+
+/// The current time, according to the mock clock of the test executor which is
+/// polling on this thread, if there is one.
+///
+/// Reflex's timing features measure time with this, rather than `Instant::now`.
+pub fn now() -> Instant {
+    #[cfg(any(test, feature = "testing"))]
+    {
+        if let Some(clock) = clock::current() {
+            return clock.now();
+        }
+    }
+    Instant::now()
+}
+
+// register a task with the mock clock of the test executor on this thread, if
+// there is one, or otherwise with the process's timer
+fn register(deadline: Instant, task: Arc<AtomicTask>) {
+    #[cfg(any(test, feature = "testing"))]
+    {
+        if let Some(clock) = clock::current() {
+            clock.register(deadline, task);
+            return;
+        }
+    }
+    timer().register(deadline, task);
+}
+
+/// Future which resolves once a deadline has passed.
+///
+/// This works on any executor, since the deadlines are tracked by a timer thread
+/// which is shared by the process, and is started once first needed. Under the mock
+/// clock of a test executor, the deadlines are tracked by that clock instead.
+pub struct Delay {
+    deadline: Instant,
+    // the task to notify at the deadline, once registered with a timer
+    registration: Option<Arc<AtomicTask>>,
+}
+
+impl Delay {
+    /// Create a delay which resolves at the given deadline.
+    pub fn new(deadline: Instant) -> Self {
+        Delay {
+            deadline,
+            registration: None,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.registration {
+            // the task is updated before the time is checked, so that a deadline
+            // which passes in between notifies the current task
+            Some(ref registration) => registration.register(),
+            None => if now() < self.deadline {
+                let registration = Arc::new(AtomicTask::new());
+                registration.register();
+                register(self.deadline, registration.clone());
+                self.registration = Some(registration);
+                return Ok(Async::NotReady);
+            },
+        };
+        if now() >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Tasks waiting for deadlines, ordered by deadline.
+#[derive(Default)]
+pub struct Deadlines {
+    pending: BinaryHeap<Reverse<Pending>>,
+    // the number of pending deadlines at which those of dropped delays are pruned
+    prune_at: usize,
+}
+
+// the fewest pending deadlines at which those of dropped delays are pruned
+const MIN_PRUNE: usize = 64;
+
+impl Deadlines {
+    /// Register a task to be notified at a deadline.
+    ///
+    /// Returns whether it is now the earliest deadline.
+    pub fn register(&mut self, deadline: Instant, task: Arc<AtomicTask>) -> bool {
+        // a dropped delay leaves its deadline pending, so those are pruned once the
+        // pending deadlines have doubled since they were last pruned
+        if self.pending.len() >= self.prune_at.max(MIN_PRUNE) {
+            self.pending.retain(|Reverse(pending)| Arc::strong_count(&pending.task) > 1);
+            self.prune_at = self.pending.len() * 2;
+        }
+        let earliest = self.next()
+            .map_or(true, |next| deadline < next);
+        self.pending.push(Reverse(Pending { deadline, task }));
+        earliest
+    }
+
+    /// The earliest deadline, if any.
+    pub fn next(&self) -> Option<Instant> {
+        self.pending.peek().map(|Reverse(next)| next.deadline)
+    }
+
+    /// The number of pending deadlines.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Notify the tasks whose deadlines have passed.
+    pub fn fire(&mut self, now: Instant) {
+        while self.next().is_some_and(|next| next <= now) {
+            if let Some(Reverse(next)) = self.pending.pop() {
+                next.task.notify();
+            }
+        }
+    }
+}
+
+struct Timer {
+    pending: Mutex<Deadlines>,
+    // notified when an earlier deadline is registered
+    wake: Condvar,
+}
+
+struct Pending {
+    deadline: Instant,
+    task: Arc<AtomicTask>,
+}
+
+// pending deadlines are ordered only by their deadline
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+// the process's timer, started upon first use
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        thread::Builder::new()
+            .name("reflex-timer".into())
+            .spawn(|| timer().work())
+            .expect("failed to spawn timer thread");
+        Timer {
+            pending: Mutex::new(Deadlines::default()),
+            wake: Condvar::new(),
+        }
+    })
+}
+
+impl Timer {
+    fn register(&self, deadline: Instant, task: Arc<AtomicTask>) {
+        if lock(&self.pending).register(deadline, task) {
+            self.wake.notify_one();
+        }
+    }
+
+    // the routine of the timer thread
+    fn work(&self) {
+        let mut pending = lock(&self.pending);
+        loop {
+            let now = Instant::now();
+            pending.fire(now);
+            pending = match pending.next() {
+                Some(next) => {
+                    let timeout = next - now;
+                    self.wake.wait_timeout(pending, timeout)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                },
+                None => self.wake.wait(pending)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+}